@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, Result};
+use std::path::{Path, PathBuf};
+
+
+/// Keys of the `[Archive]` section listing archives, in the order the engine loads them.
+/// Skyrim uses `sResourceArchiveList` and `sResourceArchiveList2`,
+/// Oblivion, Fallout 3 and New Vegas use `SArchiveList`.
+pub const ARCHIVE_LIST_KEYS: [&str; 3] = ["sResourceArchiveList", "sResourceArchiveList2", "SArchiveList"];
+
+const ARCHIVE_EXTENSIONS: [&str; 2] = ["bsa", "ba2"];
+
+/// The archives a game loads by default.
+/// These are the archives listed in the game ini files followed by the
+/// archives named after the active plugins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveList {
+    lists: [Option<Vec<String>>; 3],
+}
+impl ArchiveList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the archive lists of an ini file like `Skyrim.ini` or `Oblivion.ini`.
+    /// Lists of files read later replace the ones read before, like custom ini files do.
+    pub fn read_ini<R: BufRead>(&mut self, reader: R) -> Result<()> {
+        let mut in_archive_section = false;
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('[') {
+                in_archive_section = line.eq_ignore_ascii_case("[archive]");
+            } else if in_archive_section {
+                if let Some((key, value)) = line.split_once('=') {
+                    let key = key.trim();
+                    if let Some(i) = ARCHIVE_LIST_KEYS.iter().position(|k| k.eq_ignore_ascii_case(key)) {
+                        self.lists[i] = Some(split_list(value));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The archive names listed in the ini files in load order.
+    pub fn ini_archives(&self) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        self.lists.iter()
+            .flatten()
+            .flatten()
+            .map(String::as_str)
+            .filter(|name| seen.insert(name.to_lowercase()))
+            .collect()
+    }
+
+    /// Resolve the archives relative to the data directory.
+    /// The ini archives come first, followed by the archives of each plugin in load order,
+    /// which may include archives the game does not load, see [`plugin_archives`].
+    /// Archives that don't exist are skipped. Names are matched case insensitively.
+    pub fn resolve<P, S>(&self, data_dir: P, plugins: &[S]) -> Result<Vec<PathBuf>>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str>,
+    {
+        let data_dir = data_dir.as_ref();
+        let files = archive_files(data_dir)?;
+        let mut seen = HashSet::new();
+        let mut res = Vec::new();
+        let mut push = |name: &str| {
+            let lower = name.to_lowercase();
+            if let Some(file_name) = files.get(&lower) {
+                if seen.insert(lower) {
+                    res.push(data_dir.join(file_name));
+                }
+            }
+        };
+        for name in self.ini_archives() {
+            push(name);
+        }
+        for plugin in plugins {
+            for name in plugin_archives(plugin.as_ref(), files.keys()) {
+                push(&name);
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Names of the archives that may be loaded for a plugin, like `Plugin.bsa` and `Plugin - Textures.bsa`.
+/// `Plugin.bsa` comes first, the remaining archives are ordered by name.
+///
+/// The games differ in which suffixes they accept, so this over-approximates:
+/// all `.bsa` and `.ba2` archives named `Plugin` or `Plugin - *` are returned for every game,
+/// even though Skyrim for example only loads `Plugin.bsa` and `Plugin - Textures.bsa`.
+pub fn plugin_archives<'a, I>(plugin: &str, names: I) -> Vec<String>
+where I: IntoIterator<Item = &'a String> {
+    let plugin = plugin.to_lowercase();
+    let stem = plugin.rsplit_once('.')
+        .map_or(plugin.as_str(), |(stem, _)| stem);
+    let prefix = format!("{} - ", stem);
+
+    let mut res: Vec<String> = names.into_iter()
+        .filter(|name| match name.to_lowercase().rsplit_once('.') {
+            Some((name_stem, ext)) => ARCHIVE_EXTENSIONS.contains(&ext)
+                && (name_stem == stem || name_stem.starts_with(&prefix)),
+            None => false,
+        })
+        .cloned()
+        .collect();
+    res.sort_by_key(|name| {
+        let lower = name.to_lowercase();
+        (!lower.starts_with(&format!("{}.", stem)), lower)
+    });
+    res
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Archives in the data directory by their lowercase name.
+fn archive_files(data_dir: &Path) -> Result<HashMap<String, String>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(data_dir)? {
+        let entry = entry?;
+        if let Ok(name) = entry.file_name().into_string() {
+            if entry.file_type()?.is_file() {
+                files.insert(name.to_lowercase(), name);
+            }
+        }
+    }
+    Ok(files)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    const SKYRIM_INI: &str = "
+[General]
+sResourceArchiveList=Ignored.bsa
+
+[Archive]
+sResourceArchiveList=Skyrim - Misc.bsa, Skyrim - Shaders.bsa
+sResourceArchiveList2=Skyrim - Voices.bsa,skyrim - misc.bsa
+";
+
+    #[test]
+    fn read_ini_lists_archives_in_order() {
+        let mut list = ArchiveList::new();
+        list.read_ini(Cursor::new(SKYRIM_INI))
+            .unwrap_or_else(|err| panic!("could not read ini {}", err));
+
+        assert_eq!(list.ini_archives(), vec!["Skyrim - Misc.bsa", "Skyrim - Shaders.bsa", "Skyrim - Voices.bsa"]);
+    }
+
+    #[test]
+    fn later_ini_overrides() {
+        let mut list = ArchiveList::new();
+        list.read_ini(Cursor::new(SKYRIM_INI))
+            .unwrap_or_else(|err| panic!("could not read ini {}", err));
+        list.read_ini(Cursor::new("[Archive]\nsresourcearchivelist2=Custom.bsa\n"))
+            .unwrap_or_else(|err| panic!("could not read ini {}", err));
+
+        assert_eq!(list.ini_archives(), vec!["Skyrim - Misc.bsa", "Skyrim - Shaders.bsa", "Custom.bsa"]);
+    }
+
+    #[test]
+    fn plugin_archives_named_after_plugin() {
+        let names: Vec<String> = ["Mod - Textures.bsa", "Mod.bsa", "Modded.bsa", "Mod - Meshes.bsa", "Mod.esp"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(plugin_archives("mod.esp", &names), vec!["Mod.bsa", "Mod - Meshes.bsa", "Mod - Textures.bsa"]);
+    }
+}
@@ -0,0 +1,382 @@
+use std::io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::{DataSource, Reader, SomeReader, ReaderV001, ReaderV103, ReaderV104, ReaderV105};
+use crate::read::{Entries, File};
+use crate::write::{self, Dir, Writer};
+
+
+/// Default minimum number of bytes loaded at once.
+pub const READ_AHEAD: u64 = 64 * 1024;
+
+/// Number of chunks of [`READ_AHEAD`] bytes buffered between an async writer and its output.
+pub const WRITE_BUFFERS: usize = 16;
+
+/// Async readers for each version supported by the synchronous readers.
+/// BA2 archives are not supported by either, so there is no async reader for them.
+pub type AsyncReaderV001<R> = AsyncReader<R, ReaderV001<Loaded>>;
+pub type AsyncReaderV103<R> = AsyncReader<R, ReaderV103<Loaded>>;
+pub type AsyncReaderV104<R> = AsyncReader<R, ReaderV104<Loaded>>;
+pub type AsyncReaderV105<R> = AsyncReader<R, ReaderV105<Loaded>>;
+pub type AsyncSomeReader<R> = AsyncReader<R, SomeReader<Loaded>>;
+
+#[derive(Clone, Copy, Debug, Error)]
+#[error("{1} bytes at {0} are not loaded")]
+struct NotLoaded(u64, u64);
+
+#[derive(Debug, Error)]
+#[error("the archive was closed before it was completely written")]
+pub struct WriteAborted;
+
+#[derive(Debug, Default)]
+struct Chunks {
+    len: u64,
+    chunks: Vec<(u64, Vec<u8>)>,
+}
+
+/// The bytes of an archive loaded so far, read by the synchronous readers.
+/// Reading bytes that are not loaded fails, so they can be loaded and the read retried.
+#[derive(Clone, Debug)]
+pub struct Loaded {
+    chunks: Arc<Mutex<Chunks>>,
+    position: u64,
+}
+impl Loaded {
+    fn new(chunks: &Arc<Mutex<Chunks>>) -> Self {
+        Self {
+            chunks: Arc::clone(chunks),
+            position: 0,
+        }
+    }
+}
+impl Read for Loaded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunks = lock(&self.chunks);
+        if buf.is_empty() || self.position >= chunks.len {
+            return Ok(0);
+        }
+        let position = self.position;
+        let (start, data) = chunks.chunks.iter()
+            .find(|(start, data)| *start <= position && position < start + data.len() as u64)
+            .ok_or_else(|| io::Error::new(ErrorKind::WouldBlock, NotLoaded(position, buf.len() as u64)))?;
+        let data = &data[(position - start) as usize..];
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+impl Seek for Loaded {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            },
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => (lock(&self.chunks).len, offset),
+        };
+        self.position = if offset < 0 {
+            base.checked_sub(offset.unsigned_abs())
+        } else {
+            base.checked_add(offset as u64)
+        }.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+fn lock(chunks: &Mutex<Chunks>) -> MutexGuard<'_, Chunks> {
+    chunks.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Reads an archive from an async input using the synchronous reader `B`.
+///
+/// Parts of the archive are loaded into memory as the synchronous reader needs them,
+/// at least [`READ_AHEAD`] bytes, or the amount given to [`read_bsa_with_read_ahead`](Self::read_bsa_with_read_ahead),
+/// at a time. The index is loaded in one read before it is first accessed and stays loaded.
+/// Extracting a file loads the complete file, then writes it to the output.
+///
+/// Only loading is asynchronous. Parsing the index and decompressing files runs on the
+/// calling task and blocks its worker thread, unlike [`write_bsa`], which writes on
+/// tokio's blocking thread pool. Extracting large compressed files may stall other tasks
+/// on the same worker.
+pub struct AsyncReader<R, B> {
+    input: R,
+    chunks: Arc<Mutex<Chunks>>,
+    read_ahead: u64,
+    bsa: B,
+}
+impl<R, B> AsyncReader<R, B>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+    B: Reader<In = Loaded>,
+{
+    pub async fn read_bsa(input: R) -> io::Result<Self> {
+        Self::read_bsa_with_read_ahead(input, READ_AHEAD).await
+    }
+
+    /// Open an archive loading at least `read_ahead` bytes at a time.
+    pub async fn read_bsa_with_read_ahead(mut input: R, read_ahead: u64) -> io::Result<Self> {
+        let len = input.seek(SeekFrom::End(0)).await?;
+        let chunks = Arc::new(Mutex::new(Chunks { len, chunks: Vec::new() }));
+        let bsa = retry(&mut input, &chunks, read_ahead, || B::read_bsa(Loaded::new(&chunks))).await?;
+        Ok(Self { input, chunks, read_ahead, bsa })
+    }
+
+    pub fn header(&self) -> B::Header {
+        self.bsa.header()
+    }
+
+    pub fn set_codepage(&mut self, codepage: crate::Codepage) {
+        self.bsa.set_codepage(codepage)
+    }
+
+    pub fn with_codepage(mut self, codepage: crate::Codepage) -> Self {
+        self.set_codepage(codepage);
+        self
+    }
+
+    /// See [`Reader::entries`].
+    pub async fn entries(&mut self) -> io::Result<Entries<'_>> {
+        self.load_index().await?;
+        let bsa = &mut self.bsa;
+        retry(&mut self.input, &self.chunks, self.read_ahead, || bsa.entries().map(drop)).await?;
+        self.bsa.entries()
+    }
+
+    /// See [`Reader::list`].
+    pub async fn list(&mut self) -> io::Result<B::Root> {
+        self.load_index().await?;
+        let bsa = &mut self.bsa;
+        retry(&mut self.input, &self.chunks, self.read_ahead, || bsa.list()).await
+    }
+
+    /// See [`Reader::find`].
+    pub async fn find(&mut self, path: &str) -> io::Result<Option<File>> {
+        self.load_index().await?;
+        let bsa = &mut self.bsa;
+        retry(&mut self.input, &self.chunks, self.read_ahead, || bsa.find(path)).await
+    }
+
+    /// See [`Reader::extract`].
+    /// Decompression is CPU-bound and runs on the calling task.
+    pub async fn extract<W: AsyncWrite + Unpin>(&mut self, file: &File, mut out: W) -> io::Result<()> {
+        let index_size = self.bsa.index_size();
+        let bsa = &mut self.bsa;
+        let mut data = Vec::with_capacity(file.size);
+        retry(&mut self.input, &self.chunks, self.read_ahead.max(file.size as u64), || {
+            data.clear();
+            bsa.extract(file, &mut data)
+        }).await?;
+        lock(&self.chunks).chunks.retain(|chunk| is_index(chunk, index_size));
+        out.write_all(&data).await
+    }
+
+    /// Load the complete index in one read, replacing everything loaded before.
+    async fn load_index(&mut self) -> io::Result<()> {
+        let index_size = self.bsa.index_size();
+        if lock(&self.chunks).chunks.iter().any(|chunk| is_index(chunk, index_size)) {
+            return Ok(());
+        }
+        lock(&self.chunks).chunks.clear();
+        load(&mut self.input, &self.chunks, 0, index_size).await
+    }
+
+    /// The synchronous reader. Its operations fail if they need data that is not loaded.
+    pub fn inner(&mut self) -> &mut B {
+        &mut self.bsa
+    }
+}
+
+/// Run `op` until it doesn't need any more data, loading at least `min_len` bytes of the missing data after each attempt.
+async fn retry<R, T, F>(input: &mut R, chunks: &Mutex<Chunks>, min_len: u64, mut op: F) -> io::Result<T>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+    F: FnMut() -> io::Result<T>,
+{
+    loop {
+        let res = op();
+        match res.as_ref().err().and_then(not_loaded) {
+            Some(NotLoaded(offset, len)) => load(input, chunks, offset, len.max(min_len).max(1)).await?,
+            None => return res,
+        }
+    }
+}
+
+fn is_index((start, data): &(u64, Vec<u8>), index_size: u64) -> bool {
+    *start == 0 && data.len() as u64 >= index_size
+}
+
+fn not_loaded(err: &io::Error) -> Option<NotLoaded> {
+    err.get_ref()
+        .and_then(|err| err.downcast_ref::<NotLoaded>())
+        .copied()
+}
+
+async fn load<R>(input: &mut R, chunks: &Mutex<Chunks>, offset: u64, len: u64) -> io::Result<()>
+where R: AsyncRead + AsyncSeek + Unpin {
+    let len = len.min(lock(chunks).len.saturating_sub(offset));
+    let mut data = Vec::with_capacity(len as usize);
+    input.seek(SeekFrom::Start(offset)).await?;
+    (&mut *input).take(len).read_to_end(&mut data).await?;
+    if (data.len() as u64) < len {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, NotLoaded(offset + data.len() as u64, len - data.len() as u64)));
+    }
+    lock(chunks).chunks.push((offset, data));
+    Ok(())
+}
+
+/// Write an archive to an async output.
+///
+/// The synchronous writer runs on tokio's blocking thread pool and its output is
+/// passed on in chunks of [`READ_AHEAD`] bytes. Since the output is not seeked,
+/// the archive is written as by [`write_sequential`](write::write_sequential),
+/// so all data sources are read, and compressed, twice.
+pub async fn write_bsa<W, D, O>(writer: W, dirs: Vec<Dir<D>>, mut out: O) -> io::Result<()>
+where
+    W: Writer + Send + 'static,
+    W::Err: std::error::Error + Send + Sync + 'static,
+    D: DataSource + Send + 'static,
+    O: AsyncWrite + Unpin,
+{
+    let (sender, mut receiver) = mpsc::channel(WRITE_BUFFERS);
+    let writing = task::spawn_blocking(move || {
+        let out = BufWriter::with_capacity(READ_AHEAD as usize, Sender(sender));
+        write::write_sequential(out, |out| writer.write_bsa(dirs.iter().map(Dir::by_ref), out)
+            .map_err(|err| io::Error::new(ErrorKind::Other, err)))
+    });
+    while let Some(buf) = receiver.recv().await {
+        out.write_all(&buf).await?;
+    }
+    writing.await
+        .map_err(|err| io::Error::new(ErrorKind::Other, err))??;
+    out.flush().await
+}
+
+struct Sender(mpsc::Sender<Vec<u8>>);
+impl Write for Sender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.blocking_send(buf.to_vec())
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, WriteAborted))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::io::Cursor;
+    use tokio::runtime::Builder;
+    use crate::{ArchiveFlagV104, ArchiveFlagV105, SomeRoot, WriterV001, WriterV104, WriterV105};
+    use crate::write::test::*;
+    use super::*;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        Builder::new_current_thread()
+            .build()
+            .unwrap_or_else(|err| panic!("could not start runtime {}", err))
+            .block_on(future)
+    }
+
+    fn some_dirs() -> Vec<Dir<Vec<u8>>> {
+        vec![
+            Dir::new("a", [write::File::new("b", vec![1, 2, 3, 4]), write::File::new("c", vec![5; 100_000])]),
+            Dir::new("d\\e", [write::File::new("f", vec![6; 1000])]),
+        ]
+    }
+
+    #[test]
+    fn read_like_sync_reader() {
+        let mut v104 = WriterV104::default();
+        v104.archive_flags |= ArchiveFlagV104::EmbedFileNames;
+        let mut v105 = WriterV105::default();
+        v105.archive_flags |= ArchiveFlagV105::CompressedArchive;
+        let archives = [
+            bsa_bytes(WriterV001::default(), some_dirs()).into_inner(),
+            bsa_bytes(v104, some_dirs()).into_inner(),
+            bsa_bytes(v105, some_dirs()).into_inner(),
+        ];
+        for bytes in archives {
+            let mut sync = SomeReader::read_bsa(Cursor::new(bytes.clone()))
+                .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+            block_on(async {
+                let mut bsa = AsyncSomeReader::read_bsa(Cursor::new(bytes)).await
+                    .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+                let root = bsa.list().await
+                    .unwrap_or_else(|err| panic!("could not list bsa {}", err));
+                assert_eq!(root, sync.list().unwrap_or_else(|err| panic!("could not list bsa {}", err)), "root");
+                let files = match root {
+                    SomeRoot::V001(files) => files,
+                    SomeRoot::V10X(dirs) => dirs.into_iter().flat_map(|dir| dir.files).collect(),
+                };
+                for file in &files {
+                    let mut data = Vec::new();
+                    bsa.extract(file, &mut data).await
+                        .unwrap_or_else(|err| panic!("could not extract {} {}", file.id, err));
+                    let mut expected = Vec::new();
+                    sync.extract(file, &mut expected)
+                        .unwrap_or_else(|err| panic!("could not extract {} {}", file.id, err));
+                    assert_eq!(data, expected, "data of {}", file.id);
+                }
+                let found = bsa.find("d/e/f").await
+                    .unwrap_or_else(|err| panic!("could not find file {}", err));
+                assert!(found.is_some(), "d/e/f not found");
+            });
+        }
+    }
+
+    #[test]
+    fn read_with_tiny_read_ahead() {
+        let name = |i: u32| format!("{:08x}.txt", i.wrapping_mul(2_654_435_761));
+        let many_files = || vec![Dir::new("a", (0..3000).map(|i| write::File::new(name(i), vec![i as u8; 3])))];
+        let mut v104 = WriterV104::default();
+        v104.archive_flags |= ArchiveFlagV104::EmbedFileNames | ArchiveFlagV104::CompressedArchive;
+        let archives = [
+            bsa_bytes(WriterV001::default(), many_files()).into_inner(),
+            bsa_bytes(v104, many_files()).into_inner(),
+            bsa_bytes(WriterV105::default(), many_files()).into_inner(),
+        ];
+        for bytes in archives {
+            let mut sync = SomeReader::read_bsa(Cursor::new(bytes.clone()))
+                .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+            block_on(async {
+                let mut bsa = AsyncSomeReader::read_bsa_with_read_ahead(Cursor::new(bytes), 7).await
+                    .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+                let root = bsa.list().await
+                    .unwrap_or_else(|err| panic!("could not list bsa {}", err));
+                assert_eq!(root, sync.list().unwrap_or_else(|err| panic!("could not list bsa {}", err)), "root");
+                assert!(is_index(&lock(&bsa.chunks).chunks[0], bsa.bsa.index_size()), "index loaded at once");
+                let path = format!("a/{}", name(2999));
+                let file = bsa.find(&path).await
+                    .unwrap_or_else(|err| panic!("could not find file {}", err))
+                    .unwrap_or_else(|| panic!("{} not found", path));
+                let mut data = Vec::new();
+                bsa.extract(&file, &mut data).await
+                    .unwrap_or_else(|err| panic!("could not extract {} {}", file.id, err));
+                assert_eq!(data, vec![(2999 % 256) as u8; 3], "data of {}", file.id);
+            });
+        }
+    }
+
+    #[test]
+    fn write_like_sync_writer() {
+        let writer = || {
+            let mut writer = WriterV105::default();
+            writer.archive_flags |= ArchiveFlagV105::CompressedArchive;
+            writer
+        };
+        let mut out = Vec::new();
+        block_on(write_bsa(writer(), some_dirs(), &mut out))
+            .unwrap_or_else(|err| panic!("could not write bsa {}", err));
+        assert_eq!(out, bsa_bytes(writer(), some_dirs()).into_inner());
+    }
+}
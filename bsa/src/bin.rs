@@ -287,30 +287,56 @@ pub trait DataSource
 where Self::Read: Read {
     type Read;
     fn open(&self) -> Result<Self::Read>;
+
+    /// The number of bytes the reader will return, if it is known in advance.
+    fn size(&self) -> Option<u64> {
+        None
+    }
 }
 impl DataSource for path::Path {
     type Read = fs::File;
     fn open(&self) -> Result<Self::Read> {
         fs::File::open(self)
     }
+    fn size(&self) -> Option<u64> {
+        fs::metadata(self).ok().map(|meta| meta.len())
+    }
 }
 impl DataSource for path::PathBuf {
     type Read = fs::File;
     fn open(&self) -> Result<Self::Read> {
         fs::File::open(self)
     }
+    fn size(&self) -> Option<u64> {
+        self.as_path().size()
+    }
+}
+impl<D: DataSource + ?Sized> DataSource for &D {
+    type Read = D::Read;
+    fn open(&self) -> Result<Self::Read> {
+        (*self).open()
+    }
+    fn size(&self) -> Option<u64> {
+        (*self).size()
+    }
 }
 impl DataSource for &[u8] {
     type Read = Cursor<Vec<u8>>;
     fn open(&self) -> Result<Cursor<Vec<u8>>> {
         self.to_vec().open()
     }
+    fn size(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
 }
 impl DataSource for Vec<u8> {
     type Read = Cursor<Vec<u8>>;
     fn open(&self) -> Result<Cursor<Vec<u8>>> {
         Ok(Cursor::new(self.to_vec()))
     }
+    fn size(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
 }
 
 #[cfg(test)]
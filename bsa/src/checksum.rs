@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use crc32fast::Hasher as Crc32;
+use sha2::{Digest as _, Sha256};
+use thiserror::Error;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Reader, SomeRoot};
+use crate::read;
+
+
+/// SHA-256 digest of the uncompressed data of a file.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest(pub [u8; 32]);
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid SHA-256 digest \"{0}\"")]
+pub struct InvalidDigest(pub String);
+
+impl FromStr for Digest {
+    type Err = InvalidDigest;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidDigest(s.to_owned());
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let mut digest = [0; 32];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .map_err(|_| invalid())?;
+        }
+        Ok(Digest(digest))
+    }
+}
+#[cfg(feature = "serde")]
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Checksums of the data written through a [`ChecksumWriter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checksums {
+    pub size: u64,
+    pub crc32: u32,
+    pub sha256: Digest,
+}
+
+/// Computes checksums of everything written to it while passing it on to the inner writer.
+/// Use [`io::sink()`] as inner writer to only compute checksums.
+pub struct ChecksumWriter<W> {
+    inner: W,
+    size: u64,
+    crc32: Crc32,
+    sha256: Sha256,
+}
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            size: 0,
+            crc32: Crc32::new(),
+            sha256: Sha256::new(),
+        }
+    }
+
+    pub fn finish(self) -> Checksums {
+        Checksums {
+            size: self.size,
+            crc32: self.crc32.finalize(),
+            sha256: Digest(self.sha256.finalize().into()),
+        }
+    }
+}
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.size += n as u64;
+        self.crc32.update(&buf[..n]);
+        self.sha256.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Checksums of a file of an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChecksumEntry {
+    /// The complete path of the file using `/` as separator.
+    pub path: String,
+    /// The size of the file inside the archive.
+    pub size: usize,
+    pub uncompressed_size: u64,
+    pub compressed: bool,
+    pub crc32: u32,
+    pub sha256: Digest,
+}
+impl ChecksumEntry {
+    /// Extract a file to compute its checksums.
+    pub fn new<R: Reader>(reader: &mut R, dir: Option<&read::Dir>, file: &read::File) -> io::Result<Self> {
+        let mut out = ChecksumWriter::new(io::sink());
+        reader.extract(file, &mut out)?;
+        let checksums = out.finish();
+        Ok(Self {
+            path: read::path(dir, file),
+            size: file.size,
+            uncompressed_size: checksums.size,
+            compressed: file.compressed,
+            crc32: checksums.crc32,
+            sha256: checksums.sha256,
+        })
+    }
+
+    /// Whether both entries have the same data.
+    /// Compression and the size inside the archive are not compared.
+    pub fn same_data(&self, other: &ChecksumEntry) -> bool {
+        self.uncompressed_size == other.uncompressed_size
+            && self.crc32 == other.crc32
+            && self.sha256 == other.sha256
+    }
+}
+
+/// Checksums of all files of an archive.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChecksumManifest {
+    pub files: Vec<ChecksumEntry>,
+}
+impl ChecksumManifest {
+    /// Compute the checksums of all files in archive order.
+    pub fn new<R: Reader<Root = SomeRoot>>(reader: &mut R) -> io::Result<Self> {
+        let mut files = Vec::new();
+        match reader.list()? {
+            SomeRoot::V001(root) => for file in &root {
+                files.push(ChecksumEntry::new(reader, None, file)?);
+            },
+            SomeRoot::V10X(root) => for dir in &root {
+                for file in dir {
+                    files.push(ChecksumEntry::new(reader, Some(dir), file)?);
+                }
+            },
+        }
+        Ok(Self { files })
+    }
+
+    /// Check that `actual` contains exactly the expected files with the expected data.
+    /// Paths are compared ignoring case. Mismatches are ordered by path.
+    pub fn verify(&self, actual: &ChecksumManifest) -> Vec<Mismatch> {
+        let expected = self.by_path();
+        let mut actual = actual.by_path();
+        let mut mismatches = Vec::new();
+        for (path, expected) in expected {
+            match actual.remove(&path) {
+                Some(actual) if expected.same_data(actual) => {},
+                Some(actual) => mismatches.push(Mismatch::Different { expected: expected.clone(), actual: actual.clone() }),
+                None => mismatches.push(Mismatch::Missing(expected.clone())),
+            }
+        }
+        mismatches.extend(actual.into_values().cloned().map(Mismatch::Unexpected));
+        mismatches.sort_by_key(|mismatch| mismatch.path().to_lowercase());
+        mismatches
+    }
+
+    fn by_path(&self) -> BTreeMap<String, &ChecksumEntry> {
+        self.files.iter()
+            .map(|entry| (entry.path.to_lowercase(), entry))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    Missing(ChecksumEntry),
+    Unexpected(ChecksumEntry),
+    Different { expected: ChecksumEntry, actual: ChecksumEntry },
+}
+impl Mismatch {
+    pub fn path(&self) -> &str {
+        match self {
+            Mismatch::Missing(entry) | Mismatch::Unexpected(entry) => &entry.path,
+            Mismatch::Different { actual, .. } => &actual.path,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{SomeReader, WriterV105, ArchiveFlagV105};
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    fn manifest(dirs: Vec<Dir<Vec<u8>>>, compress: bool) -> ChecksumManifest {
+        let mut writer = WriterV105::default();
+        if compress {
+            writer.archive_flags |= ArchiveFlagV105::CompressedArchive;
+        }
+        let mut bsa = SomeReader::read_bsa(bsa_bytes(writer, dirs))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        ChecksumManifest::new(&mut bsa)
+            .unwrap_or_else(|err| panic!("could not compute checksums {}", err))
+    }
+
+    #[test]
+    fn checksums_of_uncompressed_data() {
+        let manifest = manifest(vec![Dir::new("a", [File::new("b", b"123456789".to_vec())])], true);
+
+        let entry = &manifest.files[0];
+        assert_eq!(entry.path, "a/b", "path");
+        assert!(entry.compressed, "compressed");
+        assert_eq!(entry.uncompressed_size, 9, "uncompressed_size");
+        assert_eq!(entry.crc32, 0xcbf43926, "crc32");
+        assert_eq!(entry.sha256.to_string(), "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225", "sha256");
+    }
+
+    #[test]
+    fn verify_reports_mismatches() {
+        let expected = manifest(vec![Dir::new("a", [
+            File::new("same", vec![1]),
+            File::new("changed", vec![2]),
+            File::new("missing", vec![3]),
+        ])], false);
+        let actual = manifest(vec![Dir::new("A", [
+            File::new("same", vec![1]),
+            File::new("changed", vec![4]),
+            File::new("extra", vec![5]),
+        ])], true);
+
+        let mismatches: Vec<String> = expected.verify(&actual).iter()
+            .map(|mismatch| match mismatch {
+                Mismatch::Missing(entry) => format!("missing {}", entry.path),
+                Mismatch::Unexpected(entry) => format!("unexpected {}", entry.path),
+                Mismatch::Different { actual, .. } => format!("different {}", actual.path),
+            })
+            .collect();
+        assert_eq!(mismatches, vec!["different a/changed", "unexpected a/extra", "missing a/missing"]);
+    }
+
+    #[test]
+    fn parse_digest() {
+        let digest: Digest = "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225".parse()
+            .unwrap_or_else(|err| panic!("could not parse digest {}", err));
+        assert_eq!(digest.0[0], 0x15);
+        assert!("15e2".parse::<Digest>().is_err(), "short digest");
+    }
+}
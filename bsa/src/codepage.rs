@@ -0,0 +1,155 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+use encoding_rs::Encoding;
+use thiserror::Error;
+
+use crate::Hash;
+use crate::str::StrError;
+
+
+#[derive(Debug, Error)]
+#[error("Unknown codepage {0}")]
+pub struct UnknownCodepage(pub String);
+
+/// The codepage names inside an archive are encoded with.
+/// The games store names in the ANSI codepage of the system, which is
+/// Windows-1252 for most western releases. Localized releases and mods
+/// may use other codepages like Windows-1251 or Windows-932 (Shift JIS).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Codepage(&'static Encoding);
+impl Codepage {
+    pub const WINDOWS_1250: Codepage = Codepage(encoding_rs::WINDOWS_1250);
+    pub const WINDOWS_1251: Codepage = Codepage(encoding_rs::WINDOWS_1251);
+    pub const WINDOWS_1252: Codepage = Codepage(encoding_rs::WINDOWS_1252);
+    pub const WINDOWS_932: Codepage = Codepage(encoding_rs::SHIFT_JIS);
+    pub const UTF_8: Codepage = Codepage(encoding_rs::UTF_8);
+
+    /// Find a codepage by its name, like `windows-1251`, `cp1251`, `shift_jis` or
+    /// just the codepage number `1251`.
+    pub fn for_label<S: AsRef<str>>(label: S) -> Option<Self> {
+        let label = label.as_ref().trim().to_lowercase();
+        let number = label.strip_prefix("cp").unwrap_or(&label);
+        let encoding = match number {
+            "874" => Some(encoding_rs::WINDOWS_874),
+            "932" => Some(encoding_rs::SHIFT_JIS),
+            "936" => Some(encoding_rs::GBK),
+            "949" => Some(encoding_rs::EUC_KR),
+            "950" => Some(encoding_rs::BIG5),
+            "65001" => Some(encoding_rs::UTF_8),
+            n if n.len() == 4 && n.starts_with("125") => Encoding::for_label(format!("windows-{}", n).as_bytes()),
+            _ => Encoding::for_label(label.as_bytes()),
+        };
+        encoding.map(Codepage)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    /// Encode a name.
+    /// Fails if the name contains characters that can't be represented in this codepage.
+    pub fn encode<'a>(&self, s: &'a str) -> Result<Cow<'a, [u8]>, StrError> {
+        let (bytes, _, unmappable) = self.0.encode(s);
+        if unmappable || self.0.output_encoding() != self.0 {
+            Err(StrError::Unmappable(s.to_owned(), *self))
+        } else {
+            Ok(bytes)
+        }
+    }
+
+    /// Decode a name.
+    /// Fails if the bytes are not valid in this codepage.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, str>, StrError> {
+        self.0.decode_without_bom_handling_and_without_replacement(bytes)
+            .ok_or(StrError::Malformed(*self))
+    }
+
+    /// Hash a name with the v001 hash algorithm after encoding it with this codepage.
+    /// Like the games, only ascii characters are lowercased.
+    pub fn hash_v001(&self, name: &str) -> Result<Hash, StrError> {
+        let bytes = self.encode(name)?;
+        Ok(Hash::v001_encoded(&bytes))
+    }
+
+    /// Hash a name with the v10x hash algorithm after encoding it with this codepage.
+    /// Like the games, only ascii characters are lowercased.
+    pub fn hash_v10x(&self, name: &str) -> Result<Hash, StrError> {
+        let bytes = self.encode(name)?;
+        Ok(Hash::v10x_encoded(&bytes))
+    }
+}
+impl Default for Codepage {
+    fn default() -> Self {
+        Codepage::WINDOWS_1252
+    }
+}
+impl FromStr for Codepage {
+    type Err = UnknownCodepage;
+    fn from_str(s: &str) -> Result<Self, UnknownCodepage> {
+        Codepage::for_label(s)
+            .ok_or_else(|| UnknownCodepage(s.to_owned()))
+    }
+}
+impl fmt::Display for Codepage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+impl fmt::Debug for Codepage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Codepage({})", self.name())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn for_label_finds_windows_codepages() {
+        assert_eq!(Codepage::for_label("1251"), Some(Codepage::WINDOWS_1251));
+        assert_eq!(Codepage::for_label("cp1250"), Some(Codepage::WINDOWS_1250));
+        assert_eq!(Codepage::for_label("Windows-1252"), Some(Codepage::WINDOWS_1252));
+        assert_eq!(Codepage::for_label("cp932"), Some(Codepage::WINDOWS_932));
+        assert_eq!(Codepage::for_label("shift_jis"), Some(Codepage::WINDOWS_932));
+        assert_eq!(Codepage::for_label("no such codepage"), None);
+    }
+
+    #[test]
+    fn encode_decode_identity_cyrillic() {
+        let name = "текстуры\\броня.dds";
+        let bytes = Codepage::WINDOWS_1251.encode(name)
+            .unwrap_or_else(|err| panic!("could not encode {}", err));
+
+        assert_eq!(bytes.len(), name.chars().count(), "single byte per char");
+        assert_eq!(Codepage::WINDOWS_1251.decode(&bytes).ok(), Some(name.into()));
+    }
+
+    #[test]
+    fn encode_unmappable() {
+        match Codepage::WINDOWS_1252.encode("броня") {
+            Err(StrError::Unmappable(_, cp)) => assert_eq!(cp, Codepage::WINDOWS_1252),
+            res => panic!("expected unmappable error but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn hash_ascii_independent_of_codepage() {
+        let name = "Meshes\\Armor.nif";
+        assert_eq!(Codepage::WINDOWS_1251.hash_v10x(name).ok(), Some(Hash::v10x(name)));
+        assert_eq!(Codepage::WINDOWS_932.hash_v001(name).ok(), Some(Hash::v001(name)));
+    }
+
+    #[test]
+    fn hash_lowercases_ascii_only() {
+        let name = "Броня.DDS";
+        let bytes = Codepage::WINDOWS_1251.encode(name)
+            .unwrap_or_else(|err| panic!("could not encode {}", err));
+        let hash = Codepage::WINDOWS_1251.hash_v10x(name)
+            .unwrap_or_else(|err| panic!("could not hash {}", err));
+
+        assert_eq!(hash, Hash::v10x_encoded(&bytes), "hash of encoded name");
+        assert_eq!(Codepage::WINDOWS_1251.hash_v10x("Броня.dds").ok(), Some(hash), "ascii lowercased");
+        assert_ne!(Codepage::WINDOWS_1251.hash_v10x("броня.dds").ok(), Some(hash), "non-ascii lowercased");
+    }
+}
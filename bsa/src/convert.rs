@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, Write};
+#[cfg(feature = "tar")]
+use std::io::SeekFrom;
+use std::rc::Rc;
+
+use crate::{ArchiveTree, DataSource, Reader, SomeRoot};
+use crate::progress::Observer;
+use crate::read::{self, File};
+use crate::write::{Dir, dirs_from_paths};
+#[cfg(feature = "tar")]
+use crate::write::InvalidPath;
+
+
+/// Write all files of an archive into a zip archive, compressed with deflate.
+/// Each file is extracted directly into the zip archive.
+#[cfg(feature = "zip")]
+pub fn to_zip<R, W>(reader: &mut R, out: W, observer: &mut dyn Observer) -> io::Result<W>
+where
+    R: Reader<Root = SomeRoot>,
+    W: Write + Seek,
+{
+    use zip::{CompressionMethod, ZipWriter, write::FileOptions};
+
+    let mut zip = ZipWriter::new(out);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (path, file) in files(reader, observer)? {
+        zip.start_file(path.as_str(), options).map_err(zip_error)?;
+        reader.extract_observed(&file, &mut zip, observer)?;
+    }
+    zip.finish().map_err(zip_error)
+}
+
+/// Write all files of an archive into a tar archive.
+/// Each file is extracted directly into the tar archive after a header with its uncompressed size.
+#[cfg(feature = "tar")]
+pub fn to_tar<R, W>(reader: &mut R, out: W, observer: &mut dyn Observer) -> io::Result<W>
+where
+    R: Reader<Root = SomeRoot>,
+    W: Write,
+{
+    let mut tar = tar::Builder::new(out);
+    for (path, file) in files(reader, observer)? {
+        let size = reader.uncompressed_size(&file)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        // writes only the header, including a long name entry if the path needs one
+        tar.append_data(&mut header, &path, io::empty())?;
+        reader.extract_observed(&file, tar.get_mut(), observer)?;
+        let padding = (512 - size % 512) % 512;
+        tar.get_mut().write_all(&[0; 512][..padding as usize])?;
+    }
+    tar.into_inner()
+}
+
+/// Paths of all files using `/` as separator.
+fn files<R: Reader<Root = SomeRoot>>(reader: &mut R, observer: &mut dyn Observer) -> io::Result<Vec<(String, File)>> {
+    let root = reader.list()?;
+    observer.started(ArchiveTree::new(&root).root.totals());
+    let entries = match &root {
+        SomeRoot::V001(files) => read::Entries::files(files),
+        SomeRoot::V10X(dirs) => read::Entries::dirs(dirs),
+    };
+    Ok(entries
+        .map(|(dir, file)| (read::path(dir, file).trim_matches('/').to_owned(), file.clone()))
+        .collect())
+}
+
+/// A file inside of a zip archive shared by all its files.
+/// Opening it decompresses the file into memory.
+#[cfg(feature = "zip")]
+pub struct ZipEntry<R> {
+    archive: Rc<RefCell<zip::ZipArchive<R>>>,
+    index: usize,
+    size: u64,
+}
+#[cfg(feature = "zip")]
+impl<R: Read + Seek> DataSource for ZipEntry<R> {
+    type Read = Cursor<Vec<u8>>;
+
+    fn open(&self) -> io::Result<Self::Read> {
+        let mut archive = self.archive.borrow_mut();
+        let mut file = archive.by_index(self.index).map_err(zip_error)?;
+        let mut data = Vec::with_capacity(self.size as usize);
+        file.read_to_end(&mut data)?;
+        Ok(Cursor::new(data))
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+}
+
+/// The files of a zip archive as directories to write. Directory entries are skipped.
+#[cfg(feature = "zip")]
+pub fn zip_dirs<R: Read + Seek>(zip: R) -> io::Result<Vec<Dir<ZipEntry<R>>>> {
+    let archive = Rc::new(RefCell::new(zip::ZipArchive::new(zip).map_err(zip_error)?));
+    let mut entries = Vec::new();
+    let len = archive.borrow().len();
+    for index in 0..len {
+        let mut zip = archive.borrow_mut();
+        let file = zip.by_index_raw(index).map_err(zip_error)?;
+        if file.is_dir() {
+            continue;
+        }
+        let entry = ZipEntry { archive: Rc::clone(&archive), index, size: file.size() };
+        entries.push((file.name().to_owned(), entry));
+    }
+    dirs_from_paths(entries)
+}
+
+#[cfg(feature = "zip")]
+fn zip_error(err: zip::result::ZipError) -> io::Error {
+    match err {
+        zip::result::ZipError::Io(err) => err,
+        err => io::Error::new(ErrorKind::InvalidData, err),
+    }
+}
+
+/// A file inside of a tar archive shared by all its files.
+/// Opening it reads the file into memory.
+#[cfg(feature = "tar")]
+pub struct TarEntry<R> {
+    archive: Rc<RefCell<R>>,
+    offset: u64,
+    size: u64,
+}
+#[cfg(feature = "tar")]
+impl<R: Read + Seek> DataSource for TarEntry<R> {
+    type Read = Cursor<Vec<u8>>;
+
+    fn open(&self) -> io::Result<Self::Read> {
+        let mut archive = self.archive.borrow_mut();
+        archive.seek(SeekFrom::Start(self.offset))?;
+        let mut data = vec![0; self.size as usize];
+        archive.read_exact(&mut data)?;
+        Ok(Cursor::new(data))
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.size)
+    }
+}
+
+/// The regular files of an uncompressed tar archive as directories to write.
+/// Only the headers are read, data is read when the files are written.
+#[cfg(feature = "tar")]
+pub fn tar_dirs<R: Read + Seek>(tar: R) -> io::Result<Vec<Dir<TarEntry<R>>>> {
+    let mut archive = tar::Archive::new(tar);
+    let mut files = Vec::new();
+    for entry in archive.entries_with_seek()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            let path = entry.path()?;
+            let path = path.to_str()
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, InvalidPath(path.to_string_lossy().into_owned())))?
+                .to_owned();
+            files.push((path, entry.raw_file_position(), entry.size()));
+        }
+    }
+    let archive = Rc::new(RefCell::new(archive.into_inner()));
+    dirs_from_paths(files.into_iter()
+        .map(|(path, offset, size)| (path, TarEntry { archive: Rc::clone(&archive), offset, size }))
+        .collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{SomeReader, Writer, WriterV105, ArchiveFlagV105};
+    use crate::write::{self, test::*};
+    use super::*;
+
+    fn some_dirs() -> Vec<Dir<Vec<u8>>> {
+        vec![
+            Dir::new("", [write::File::new("readme.txt", b"read me".to_vec())]),
+            Dir::new("meshes\\armor", [write::File::new("a.nif", vec![1; 100]), write::File::new("b.nif", vec![2, 3])]),
+        ]
+    }
+
+    fn some_bsa() -> SomeReader<Cursor<Vec<u8>>> {
+        let mut writer = WriterV105::default();
+        writer.archive_flags |= ArchiveFlagV105::CompressedArchive;
+        SomeReader::read_bsa(bsa_bytes(writer, some_dirs()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err))
+    }
+
+    fn assert_round_trip<D: DataSource>(dirs: Vec<Dir<D>>) {
+        let mut out = Cursor::new(Vec::<u8>::new());
+        WriterV105::default().write_bsa(dirs, &mut out)
+            .unwrap_or_else(|err| panic!("could not write bsa {}", err));
+        let mut bsa = SomeReader::read_bsa(Cursor::new(out.into_inner()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+
+        let mut expected = some_bsa();
+        let diff = crate::Diff::new(&mut expected, &mut bsa)
+            .unwrap_or_else(|err| panic!("could not diff {}", err));
+        let changes: Vec<&str> = diff.changes.iter()
+            .filter(|change| !matches!(change, crate::diff::Change::Modified { old, new } if old.same_data(new)))
+            .map(|change| change.path())
+            .collect();
+        assert_eq!(changes, Vec::<&str>::new(), "changes");
+        assert_eq!(diff.changes.len() + diff.unchanged, 3, "files");
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_round_trip() {
+        let zip = to_zip(&mut some_bsa(), Cursor::new(Vec::new()), &mut ())
+            .unwrap_or_else(|err| panic!("could not write zip {}", err));
+        let dirs = zip_dirs(Cursor::new(zip.into_inner()))
+            .unwrap_or_else(|err| panic!("could not read zip {}", err));
+        assert_eq!(dirs.iter().map(|dir| dir.name.as_str()).collect::<Vec<_>>(), vec!["", "meshes\\armor"]);
+        assert_round_trip(dirs);
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn tar_round_trip() {
+        let tar = to_tar(&mut some_bsa(), Vec::new(), &mut ())
+            .unwrap_or_else(|err| panic!("could not write tar {}", err));
+        let dirs = tar_dirs(Cursor::new(tar))
+            .unwrap_or_else(|err| panic!("could not read tar {}", err));
+        assert_eq!(dirs.iter().map(|dir| dir.name.as_str()).collect::<Vec<_>>(), vec!["", "meshes\\armor"]);
+        assert_round_trip(dirs);
+    }
+}
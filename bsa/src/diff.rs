@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::io;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{Hash, Reader, SomeRoot};
+use crate::checksum::ChecksumEntry;
+use crate::read;
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(tag = "change", rename_all = "lowercase"))]
+pub enum Change {
+    Added(ChecksumEntry),
+    Removed(ChecksumEntry),
+    /// The data or the compression of the file changed.
+    Modified { old: ChecksumEntry, new: ChecksumEntry },
+    /// The file was removed and a file with the same data was added.
+    Renamed { old: ChecksumEntry, new: ChecksumEntry },
+}
+impl Change {
+    /// The path of the file in the new archive or the old one if it was removed.
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added(entry) | Change::Removed(entry) => &entry.path,
+            Change::Modified { new, .. } | Change::Renamed { new, .. } => &new.path,
+        }
+    }
+}
+
+/// Differences between the files of two archives.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Diff {
+    /// All changes ordered by path.
+    pub changes: Vec<Change>,
+    /// Number of files that are the same in both archives.
+    pub unchanged: usize,
+}
+impl Diff {
+    /// Compare the files of two archives.
+    ///
+    /// If both archives are v10x archives files are matched by the hashes of their directory and name,
+    /// otherwise, since v001 uses a different hash, they are matched by their complete path ignoring case.
+    /// The data of every file is extracted to compare it.
+    pub fn new<A, B>(old: &mut A, new: &mut B) -> io::Result<Self>
+    where
+        A: Reader<Root = SomeRoot>,
+        B: Reader<Root = SomeRoot>,
+    {
+        let old_root = old.list()?;
+        let new_root = new.list()?;
+        let by_hash = matches!((&old_root, &new_root), (SomeRoot::V10X(_), SomeRoot::V10X(_)));
+        let mut old_entries = entries(old, &old_root, by_hash)?;
+        let mut new_entries = entries(new, &new_root, by_hash)?;
+
+        let mut diff = Diff::default();
+        let mut removed = Vec::new();
+        for (key, old_entry) in old_entries.iter() {
+            match new_entries.get(key) {
+                Some(new_entry) if new_entry.same_data(old_entry) && new_entry.compressed == old_entry.compressed =>
+                    diff.unchanged += 1,
+                Some(new_entry) => diff.changes.push(Change::Modified { old: old_entry.clone(), new: new_entry.clone() }),
+                None => removed.push(key.clone()),
+            }
+        }
+        new_entries.retain(|key, _| !old_entries.contains_key(key));
+        for key in removed {
+            let old_entry = old_entries.remove(&key).unwrap_or_else(|| unreachable!());
+            let renamed_to = new_entries.iter()
+                .find(|(_, new_entry)| new_entry.same_data(&old_entry))
+                .map(|(key, _)| key.clone());
+            match renamed_to.and_then(|key| new_entries.remove(&key)) {
+                Some(new_entry) => diff.changes.push(Change::Renamed { old: old_entry, new: new_entry }),
+                None => diff.changes.push(Change::Removed(old_entry)),
+            }
+        }
+        diff.changes.extend(new_entries.into_values().map(Change::Added));
+        diff.changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(diff)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Hashes(Hash, Hash),
+    Path(String),
+}
+
+fn entries<R: Reader>(reader: &mut R, root: &SomeRoot, by_hash: bool) -> io::Result<BTreeMap<Key, ChecksumEntry>> {
+    let mut res = BTreeMap::new();
+    let mut push = |reader: &mut R, dir: Option<&read::Dir>, file: &read::File| -> io::Result<()> {
+        let entry = ChecksumEntry::new(reader, dir, file)?;
+        let key = match dir {
+            Some(dir) if by_hash => Key::Hashes(dir.id.hash, file.id.hash),
+            _ => Key::Path(entry.path.to_lowercase()),
+        };
+        res.insert(key, entry);
+        Ok(())
+    };
+    match root {
+        SomeRoot::V001(files) => for file in files {
+            push(reader, None, file)?;
+        },
+        SomeRoot::V10X(dirs) => for dir in dirs {
+            for file in dir {
+                push(reader, Some(dir), file)?;
+            }
+        },
+    }
+    Ok(res)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::{SomeReader, WriterV104, WriterV105};
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    fn open(bytes: Cursor<Vec<u8>>) -> SomeReader<Cursor<Vec<u8>>> {
+        SomeReader::read_bsa(bytes)
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err))
+    }
+
+    #[test]
+    fn diff_across_versions() {
+        let old = bsa_bytes(WriterV104::default(), vec![
+            Dir::new("a", [
+                File::new("same", vec![1]),
+                File::new("changed", vec![2]),
+                File::new("gone", vec![3]),
+                File::new("moved", vec![4, 4]),
+            ]),
+        ]);
+        let new = bsa_bytes(WriterV105::default(), vec![
+            Dir::new("a", [
+                File::new("same", vec![1]),
+                File::new("changed", vec![2, 2]),
+            ]),
+            Dir::new("b", [
+                File::new("moved", vec![4, 4]),
+                File::new("new", vec![5]),
+            ]),
+        ]);
+
+        let diff = Diff::new(&mut open(old), &mut open(new))
+            .unwrap_or_else(|err| panic!("could not diff {}", err));
+
+        assert_eq!(diff.unchanged, 1, "unchanged");
+        let changes: Vec<String> = diff.changes.iter()
+            .map(|change| match change {
+                Change::Added(entry) => format!("A {}", entry.path),
+                Change::Removed(entry) => format!("D {}", entry.path),
+                Change::Modified { new, .. } => format!("M {}", new.path),
+                Change::Renamed { old, new } => format!("R {} {}", old.path, new.path),
+            })
+            .collect();
+        assert_eq!(changes, vec!["M a/changed", "D a/gone", "R a/moved b/moved", "A b/new"]);
+    }
+}
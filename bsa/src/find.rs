@@ -0,0 +1,196 @@
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use glob::{MatchOptions, Pattern};
+use thiserror::Error;
+
+use crate::{Codepage, Hash, Reader, SomeReader};
+use crate::read::{self, Dir, File};
+
+
+/// Number of archives searched at the same time by [`find`].
+pub const THREADS: usize = 8;
+
+#[derive(Debug, Error)]
+#[error("searching {0} failed unexpectedly")]
+pub struct SearchFailed(pub PathBuf);
+
+/// Decides which files of an archive are found.
+pub trait Matcher: Send + Sync {
+    /// `path` is the complete path of the file as returned by [`read::path`].
+    fn matches(&self, path: &str, dir: Option<&Dir>, file: &File) -> bool;
+}
+/// Matches the complete path ignoring case. `*` also matches `/`.
+impl Matcher for Pattern {
+    fn matches(&self, path: &str, _dir: Option<&Dir>, _file: &File) -> bool {
+        const MATCH_OPTS: MatchOptions = MatchOptions {
+            case_sensitive: false,
+            require_literal_leading_dot: false,
+            require_literal_separator: false,
+        };
+        self.matches_with(path.trim_matches('/'), MATCH_OPTS)
+    }
+}
+/// Matches files with this hash and all files of directories with this hash.
+/// This also finds files without names.
+impl Matcher for Hash {
+    fn matches(&self, _path: &str, dir: Option<&Dir>, file: &File) -> bool {
+        file.id.hash == *self || dir.map_or(false, |dir| dir.id.hash == *self)
+    }
+}
+impl<F> Matcher for F
+where F: Fn(&str, Option<&Dir>, &File) -> bool + Send + Sync {
+    fn matches(&self, path: &str, dir: Option<&Dir>, file: &File) -> bool {
+        self(path, dir, file)
+    }
+}
+
+/// A file found in an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Found {
+    pub path: String,
+    pub file: File,
+    pub uncompressed_size: u64,
+}
+
+/// The files found in an archive, or why it could not be searched.
+#[derive(Debug)]
+pub struct ArchiveMatches {
+    pub archive: PathBuf,
+    pub found: io::Result<Vec<Found>>,
+}
+
+/// Find all matching files of an archive.
+pub fn find_in<R: Reader, M: Matcher + ?Sized>(reader: &mut R, matcher: &M) -> io::Result<Vec<Found>> {
+    let matching: Vec<(String, File)> = reader.entries()?
+        .map(|(dir, file)| (read::path(dir, file), dir, file))
+        .filter(|(path, dir, file)| matcher.matches(path, *dir, file))
+        .map(|(path, _, file)| (path, file.clone()))
+        .collect();
+    matching.into_iter()
+        .map(|(path, file)| {
+            let uncompressed_size = reader.uncompressed_size(&file)?;
+            Ok(Found { path, file, uncompressed_size })
+        })
+        .collect()
+}
+
+/// Search many archives in parallel using up to [`THREADS`] threads.
+/// Results are in the order of `archives`. An archive that can't be read doesn't stop the search.
+pub fn find<P, M>(archives: &[P], matcher: M, codepage: Codepage) -> Vec<ArchiveMatches>
+where
+    P: AsRef<Path>,
+    M: Matcher + 'static,
+{
+    let matcher = Arc::new(matcher);
+    let mut jobs: Vec<(usize, PathBuf)> = archives.iter()
+        .map(|path| path.as_ref().to_path_buf())
+        .enumerate()
+        .collect();
+    jobs.reverse();
+    let jobs = Arc::new(Mutex::new(jobs));
+    let (results, received) = mpsc::channel();
+    for _ in 0..archives.len().min(THREADS) {
+        let matcher = Arc::clone(&matcher);
+        let jobs = Arc::clone(&jobs);
+        let results = results.clone();
+        thread::spawn(move || {
+            while let Some((i, archive)) = jobs.lock().ok().and_then(|mut jobs| jobs.pop()) {
+                let found = read::open::<SomeReader<_>, _>(&archive)
+                    .and_then(|bsa| find_in(&mut bsa.with_codepage(codepage), &*matcher));
+                if results.send((i, ArchiveMatches { archive, found })).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(results);
+
+    let mut matches: Vec<Option<ArchiveMatches>> = archives.iter().map(|_| None).collect();
+    for (i, archive_matches) in received {
+        matches[i] = Some(archive_matches);
+    }
+    // an archive is missing if reading it panicked
+    matches.into_iter()
+        .zip(archives)
+        .map(|(archive_matches, archive)| archive_matches.unwrap_or_else(|| ArchiveMatches {
+            archive: archive.as_ref().to_path_buf(),
+            found: Err(io::Error::new(ErrorKind::Other, SearchFailed(archive.as_ref().to_path_buf()))),
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::{ArchiveFlagV104, ArchiveFlagV105, WriterV001, WriterV104, WriterV105};
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    #[test]
+    fn find_in_many_archives() {
+        let dir = temp_dir("bsa-find-archives");
+        fs::create_dir_all(&dir)
+            .unwrap_or_else(|err| panic!("could not create dir {}", err));
+        let archives = vec![dir.join("a.bsa"), dir.join("b.bsa"), dir.join("missing.bsa")];
+        fs::write(&archives[0], bsa_bytes(WriterV105::default(), vec![
+            Dir::new("textures\\x", [File::new("y.dds", vec![1, 2]), File::new("z.dds", vec![3])]),
+        ]).into_inner()).unwrap_or_else(|err| panic!("could not write bsa {}", err));
+        fs::write(&archives[1], bsa_bytes(WriterV001::default(), vec![
+            Dir::new("textures\\x", [File::new("y.dds", vec![4, 5, 6])]),
+        ]).into_inner()).unwrap_or_else(|err| panic!("could not write bsa {}", err));
+
+        let pattern = Pattern::new("Textures/X/Y.*")
+            .unwrap_or_else(|err| panic!("invalid pattern {}", err));
+        let matches = find(&archives, pattern, Codepage::default());
+        fs::remove_dir_all(&dir)
+            .unwrap_or_else(|err| panic!("could not remove dir {}", err));
+
+        let found: Vec<Vec<(String, u64)>> = matches[..2].iter()
+            .map(|archive_matches| archive_matches.found.as_ref()
+                .unwrap_or_else(|err| panic!("could not search {} {}", archive_matches.archive.display(), err))
+                .iter()
+                .map(|found| (found.path.clone(), found.uncompressed_size))
+                .collect())
+            .collect();
+        assert_eq!(found, vec![
+            vec![("textures/x/y.dds".to_owned(), 2)],
+            vec![("textures/x/y.dds".to_owned(), 3)],
+        ]);
+        assert_eq!(matches[2].archive, archives[2], "archive");
+        assert!(matches[2].found.is_err(), "missing archive found");
+    }
+
+    #[test]
+    fn find_by_hash() {
+        let mut bsa = SomeReader::read_bsa(bsa_bytes(WriterV105::default(), some_bsa_dirs()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let hash = Hash::v10x("b");
+        let found = find_in(&mut bsa, &hash)
+            .unwrap_or_else(|err| panic!("could not search {}", err));
+        assert_eq!(found.len(), 1, "found");
+        assert_eq!(found[0].file.id.hash, hash, "hash");
+    }
+
+    #[test]
+    fn uncompressed_size_of_compressed_files() {
+        let dirs = || vec![Dir::new("meshes", [File::new("a.nif", vec![7; 1000]), File::new("b.nif", vec![1, 2, 3])])];
+        let mut v104 = WriterV104::default();
+        v104.archive_flags |= ArchiveFlagV104::CompressedArchive | ArchiveFlagV104::EmbedFileNames;
+        let mut v105 = WriterV105::default();
+        v105.archive_flags |= ArchiveFlagV105::CompressedArchive;
+        for bytes in [bsa_bytes(v104, dirs()), bsa_bytes(v105, dirs())] {
+            let mut bsa = SomeReader::read_bsa(bytes)
+                .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+            let pattern = Pattern::new("meshes/*")
+                .unwrap_or_else(|err| panic!("invalid pattern {}", err));
+            let found = find_in(&mut bsa, &pattern)
+                .unwrap_or_else(|err| panic!("could not search {}", err));
+            assert!(found.iter().all(|found| found.file.compressed), "compressed");
+            let sizes: Vec<u64> = found.iter().map(|found| found.uncompressed_size).collect();
+            assert_eq!(sizes, vec![1000, 3], "sizes");
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use std::{fmt, hash};
+use std::str::FromStr;
 use bytemuck::{Zeroable, Pod};
+use thiserror::Error;
 
 use crate::bin::concat_bytes;
 use crate::bin::{derive_readable_via_pod, derive_writable_via_pod};
@@ -17,12 +19,27 @@ derive_readable_via_pod!(Hash);
 derive_writable_via_pod!(Hash);
 impl Hash {
 
+    /// Convert between the little-endian and big-endian encoding of the hash as a `u64`.
+    pub(crate) fn swap_bytes(self) -> Self {
+        Self {
+            low: self.high.swap_bytes(),
+            high: self.low.swap_bytes(),
+        }
+    }
+
     /// Hash the name using the v001 hash algorithm.
     /// See https://en.uesp.net/wiki/Morrowind_Mod:BSA_File_Format#Hash_calculation.
     pub fn v001<S>(s: S) -> Self
     where S: AsRef<str> {
-        let path = sanitize(s);
-        let bytes = path.as_bytes();
+        Self::v001_encoded(s.as_ref().as_bytes())
+    }
+
+    /// Hash a name that is already encoded with the codepage of the archive
+    /// using the v001 hash algorithm.
+    /// Only ascii characters are lowercased.
+    pub fn v001_encoded(name: &[u8]) -> Self {
+        let path = sanitize(name);
+        let bytes = path.as_slice();
         let mid_point = bytes.len() >> 1;
     
         Self {
@@ -45,15 +62,27 @@ impl Hash {
     /// See https://en.uesp.net/wiki/Oblivion_Mod:Hash_Calculation
     pub fn v10x<S>(s: S) -> Self
     where S: AsRef<str> {
-        let path = sanitize(s);
-        let (root, ext) = hash_v10x_parts(path.as_bytes());
+        Self::v10x_encoded(s.as_ref().as_bytes())
+    }
+
+    /// Hash a name that is already encoded with the codepage of the archive
+    /// using the v10x hash algorithm.
+    /// Only ascii characters are lowercased.
+    /// The empty name of the root directory hashes to zero.
+    /// Names without a stem, like `.gitignore`, are hashed by their extension.
+    pub fn v10x_encoded(name: &[u8]) -> Self {
+        if name.is_empty() {
+            return Self { low: 0, high: 0 };
+        }
+        let path = sanitize(name);
+        let (root, ext) = hash_v10x_parts(path.as_slice());
 
         Self {
             low: concat_bytes([
-                root[root.len() - 1],
+                root.last().copied().unwrap_or(0),
                 when(root.len() > 2, || root[root.len() - 2]),
                 root.len() as u8,
-                root[0],
+                root.first().copied().unwrap_or(0),
             ]) | match &*ext {
                 b".nif" => 0x00008000,
                 b".kf"  => 0x00000080,
@@ -73,6 +102,41 @@ impl fmt::Display for Hash {
         write!(f, "{:08x}{:08x}", self.low, self.high)
     }
 }
+
+#[derive(Debug, Error)]
+#[error("invalid hash \"{0}\", expected 16 hexadecimal digits")]
+pub struct InvalidHash(pub String);
+
+/// Parses hashes as they are displayed, optionally prefixed with `#` like names of entries without a name.
+impl FromStr for Hash {
+    type Err = InvalidHash;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidHash(s.to_owned());
+        let digits = s.strip_prefix('#').unwrap_or(s);
+        if digits.len() != 16 || !digits.is_ascii() {
+            return Err(invalid());
+        }
+        let low = u32::from_str_radix(&digits[..8], 16).map_err(|_| invalid())?;
+        let high = u32::from_str_radix(&digits[8..], 16).map_err(|_| invalid())?;
+        Ok(Hash { low, high })
+    }
+}
+#[cfg(feature = "serde")]
+impl serde::Serialize for Hash {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Hash {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, Unexpected};
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| D::Error::invalid_value(Unexpected::Str(&s), &"16 hexadecimal digits"))
+    }
+}
 impl hash::Hash for Hash {
     fn hash<H: hash::Hasher>(&self, state: &mut H) {
         state.write_u32(self.low);
@@ -80,11 +144,13 @@ impl hash::Hash for Hash {
     }
 }
 
-fn sanitize<S>(path: S) -> String
-where S: AsRef<str> {
-    path.as_ref()
-        .to_lowercase()
-        .replace('/', "\\")
+fn sanitize(path: &[u8]) -> Vec<u8> {
+    path.iter()
+        .map(|c| match c {
+            b'/' => b'\\',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
 }
 
 fn hash_v10x_parts(bytes: &[u8]) -> (&[u8], &[u8]) {
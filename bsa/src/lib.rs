@@ -3,10 +3,27 @@
 #[macro_use]
 mod bin;
 mod compress;
+mod codepage;
 mod str;
 pub mod read;
+pub mod archive_list;
 pub mod write;
+pub mod scan;
+pub mod progress;
 mod hash;
+pub mod names;
+#[cfg(feature = "serde")]
+pub mod manifest;
+pub mod tree;
+pub mod diff;
+pub mod checksum;
+pub mod find;
+pub mod stats;
+#[cfg(any(feature = "zip", feature = "tar"))]
+pub mod convert;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod vfs;
 pub mod version;
 pub mod v001;
 mod v10x;
@@ -18,11 +35,20 @@ use std::io::{self, Read, Seek, Write};
 use bin::ReadableFixed;
 use thiserror::Error;
 
-pub use crate::hash::Hash;
+pub use crate::hash::{Hash, InvalidHash};
+pub use crate::codepage::{Codepage, UnknownCodepage};
 pub use crate::version::*;
 pub use crate::bin::DataSource;
-pub use crate::read::{open, Reader, EntryId};
+pub use crate::names::Names;
+pub use crate::tree::ArchiveTree;
+pub use crate::diff::Diff;
+pub use crate::checksum::ChecksumManifest;
+pub use crate::archive_list::ArchiveList;
+pub use crate::vfs::Vfs;
+pub use crate::read::{open, Entries, Reader, EntryId};
 pub use crate::write::{list_dir, Writer};
+pub use crate::scan::Scanner;
+pub use crate::progress::{CancelToken, Observer};
 pub use crate::v001::{V001, ReaderV001, HeaderV001, WriterV001};
 pub use crate::v10x::{ToArchiveBitFlags, FileFlag};
 pub use crate::v103::{V103, ReaderV103, HeaderV103, WriterV103, ArchiveFlagV103};
@@ -30,7 +56,9 @@ pub use crate::v104::{V104, ReaderV104, HeaderV104, WriterV104, ArchiveFlagV104}
 pub use crate::v105::{V105, ReaderV105, HeaderV105, WriterV105, ArchiveFlagV105};
 
 
+/// Serialized as the contained value without the version.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(untagged))]
 pub enum ForSomeVersion<A001, A10X> {
     #[error("{0}")] V001(A001),
     #[error("{0}")] V10X(A10X),
@@ -45,6 +73,7 @@ impl<A001, A103, A104, A105> ForSomeVersion<A001, ForSomeVersion10X<A103, A104,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(untagged))]
 pub enum ForSomeVersion10X<A103, A104, A105> {
     #[error("{0}")] V103(A103),
     #[error("{0}")] V104(A104),
@@ -62,6 +91,27 @@ impl<A103, A104, A105> ForSomeVersion10X<A103, A104, A105> {
 
 pub type SomeHeaderV10X = ForSomeVersion10X<HeaderV103, HeaderV104, HeaderV105>;
 pub type SomeHeader = ForSomeVersion<HeaderV001, SomeHeaderV10X>;
+impl SomeHeader {
+    /// Whether the archive stores a table of directory names. v001 archives have no directories.
+    pub fn includes_dir_names(&self) -> bool {
+        match self {
+            ForSomeVersion::V001(_) => false,
+            ForSomeVersion::V10X(ForSomeVersion10X::V103(header)) => header.archive_flags.contains(ArchiveFlagV103::IncludeDirectoryNames),
+            ForSomeVersion::V10X(ForSomeVersion10X::V104(header)) => header.archive_flags.contains(ArchiveFlagV104::IncludeDirectoryNames),
+            ForSomeVersion::V10X(ForSomeVersion10X::V105(header)) => header.archive_flags.contains(ArchiveFlagV105::IncludeDirectoryNames),
+        }
+    }
+
+    /// Whether the archive stores a table of file names. v001 archives always do.
+    pub fn includes_file_names(&self) -> bool {
+        match self {
+            ForSomeVersion::V001(_) => true,
+            ForSomeVersion::V10X(ForSomeVersion10X::V103(header)) => header.archive_flags.contains(ArchiveFlagV103::IncludeFileNames),
+            ForSomeVersion::V10X(ForSomeVersion10X::V104(header)) => header.archive_flags.contains(ArchiveFlagV104::IncludeFileNames),
+            ForSomeVersion::V10X(ForSomeVersion10X::V105(header)) => header.archive_flags.contains(ArchiveFlagV105::IncludeFileNames),
+        }
+    }
+}
 
 pub type SomeReaderV10X<R> = ForSomeVersion10X<ReaderV103<R>, ReaderV104<R>, ReaderV105<R>>;
 pub type SomeReader<R> = ForSomeVersion<ReaderV001<R>, SomeReaderV10X<R>>;
@@ -89,6 +139,34 @@ where R: Read + Seek {
         }
     }
 
+    fn codepage(&self) -> Codepage {
+        match self {
+            ForSomeVersion::V001(bsa) => bsa.codepage(),
+            ForSomeVersion::V10X(bsa) => bsa.codepage(),
+        }
+    }
+
+    fn index_size(&self) -> u64 {
+        match self {
+            ForSomeVersion::V001(bsa) => bsa.index_size(),
+            ForSomeVersion::V10X(bsa) => bsa.index_size(),
+        }
+    }
+
+    fn set_codepage(&mut self, codepage: Codepage) {
+        match self {
+            ForSomeVersion::V001(bsa) => bsa.set_codepage(codepage),
+            ForSomeVersion::V10X(bsa) => bsa.set_codepage(codepage),
+        }
+    }
+
+    fn entries(&mut self) -> io::Result<read::Entries<'_>> {
+        match self {
+            ForSomeVersion::V001(bsa) => bsa.entries(),
+            ForSomeVersion::V10X(bsa) => bsa.entries(),
+        }
+    }
+
     fn list(&mut self) -> io::Result<SomeRoot> {
         match self {
             ForSomeVersion::V001(bsa) => bsa.list().map(SomeRoot::V001),
@@ -102,6 +180,13 @@ where R: Read + Seek {
             ForSomeVersion::V10X(bsa) => bsa.extract(file, writer),
         }
     }
+
+    fn uncompressed_size(&mut self, file: &read::File) -> io::Result<u64> {
+        match self {
+            ForSomeVersion::V001(bsa) => bsa.uncompressed_size(file),
+            ForSomeVersion::V10X(bsa) => bsa.uncompressed_size(file),
+        }
+    }
 }
 
 
@@ -124,6 +209,38 @@ where R: Read + Seek {
         }
     }
 
+    fn codepage(&self) -> Codepage {
+        match self {
+            ForSomeVersion10X::V103(bsa) => bsa.codepage(),
+            ForSomeVersion10X::V104(bsa) => bsa.codepage(),
+            ForSomeVersion10X::V105(bsa) => bsa.codepage(),
+        }
+    }
+
+    fn index_size(&self) -> u64 {
+        match self {
+            ForSomeVersion10X::V103(bsa) => bsa.index_size(),
+            ForSomeVersion10X::V104(bsa) => bsa.index_size(),
+            ForSomeVersion10X::V105(bsa) => bsa.index_size(),
+        }
+    }
+
+    fn set_codepage(&mut self, codepage: Codepage) {
+        match self {
+            ForSomeVersion10X::V103(bsa) => bsa.set_codepage(codepage),
+            ForSomeVersion10X::V104(bsa) => bsa.set_codepage(codepage),
+            ForSomeVersion10X::V105(bsa) => bsa.set_codepage(codepage),
+        }
+    }
+
+    fn entries(&mut self) -> io::Result<read::Entries<'_>> {
+        match self {
+            ForSomeVersion10X::V103(bsa) => bsa.entries(),
+            ForSomeVersion10X::V104(bsa) => bsa.entries(),
+            ForSomeVersion10X::V105(bsa) => bsa.entries(),
+        }
+    }
+
     fn list(&mut self) -> io::Result<Vec<read::Dir>> {
         match self {
             ForSomeVersion10X::V103(bsa) => bsa.list(),
@@ -139,6 +256,14 @@ where R: Read + Seek {
             ForSomeVersion10X::V105(bsa) => bsa.extract(file, writer),
         }
     }
+
+    fn uncompressed_size(&mut self, file: &read::File) -> io::Result<u64> {
+        match self {
+            ForSomeVersion10X::V103(bsa) => bsa.uncompressed_size(file),
+            ForSomeVersion10X::V104(bsa) => bsa.uncompressed_size(file),
+            ForSomeVersion10X::V105(bsa) => bsa.uncompressed_size(file),
+        }
+    }
 }
 
 impl Writer for SomeWriter {
@@ -151,19 +276,34 @@ impl Writer for SomeWriter {
         >,
     >;
 
-    fn write_bsa<DS, D, W>(&self, dirs: DS, out: W) -> Result<(), Self::Err>
+    fn write_bsa_observed<DS, D, W>(&self, dirs: DS, out: W, observer: &mut dyn Observer) -> Result<(), Self::Err>
     where
         D: bin::DataSource,
         DS: IntoIterator<Item = write::Dir<D>>,
         W: Write + Seek {
             match self {
-                ForSomeVersion::V001(writer) => writer.write_bsa(dirs, out).map_err(ForSomeVersion::V001),
-                ForSomeVersion::V10X(writer) => writer.write_bsa(dirs, out).map_err(ForSomeVersion::V10X),
+                ForSomeVersion::V001(writer) => writer.write_bsa_observed(dirs, out, observer).map_err(ForSomeVersion::V001),
+                ForSomeVersion::V10X(writer) => writer.write_bsa_observed(dirs, out, observer).map_err(ForSomeVersion::V10X),
             }
     }
 }
 
 
+impl SomeWriterV10X {
+    /// Write an archive and report how much space was saved by deduplication.
+    pub fn write_bsa_with_stats<DS, D, W>(&self, dirs: DS, out: W, observer: &mut dyn Observer) -> io::Result<write::WriteStats>
+    where
+        D: bin::DataSource,
+        DS: IntoIterator<Item = write::Dir<D>>,
+        W: Write + Seek {
+        match self {
+            ForSomeVersion10X::V103(writer) => writer.write_bsa_with_stats(dirs, out, observer),
+            ForSomeVersion10X::V104(writer) => writer.write_bsa_with_stats(dirs, out, observer),
+            ForSomeVersion10X::V105(writer) => writer.write_bsa_with_stats(dirs, out, observer),
+        }
+    }
+}
+
 impl Writer for SomeWriterV10X {
     type Err = ForSomeVersion10X<
         <WriterV103 as Writer>::Err,
@@ -171,15 +311,15 @@ impl Writer for SomeWriterV10X {
         <WriterV105 as Writer>::Err,
     >;
 
-    fn write_bsa<DS, D, W>(&self, dirs: DS, out: W) -> Result<(), Self::Err>
+    fn write_bsa_observed<DS, D, W>(&self, dirs: DS, out: W, observer: &mut dyn Observer) -> Result<(), Self::Err>
     where
         D: bin::DataSource,
         DS: IntoIterator<Item = write::Dir<D>>,
         W: Write + Seek {
             match self {
-                ForSomeVersion10X::V103(writer) => writer.write_bsa(dirs, out).map_err(ForSomeVersion10X::V103),
-                ForSomeVersion10X::V104(writer) => writer.write_bsa(dirs, out).map_err(ForSomeVersion10X::V104),
-                ForSomeVersion10X::V105(writer) => writer.write_bsa(dirs, out).map_err(ForSomeVersion10X::V105),
+                ForSomeVersion10X::V103(writer) => writer.write_bsa_observed(dirs, out, observer).map_err(ForSomeVersion10X::V103),
+                ForSomeVersion10X::V104(writer) => writer.write_bsa_observed(dirs, out, observer).map_err(ForSomeVersion10X::V104),
+                ForSomeVersion10X::V105(writer) => writer.write_bsa_observed(dirs, out, observer).map_err(ForSomeVersion10X::V105),
             }
     }
 }
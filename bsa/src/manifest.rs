@@ -0,0 +1,292 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use enumflags2::{BitFlag, BitFlags};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{Codepage, EntryId, ForSomeVersion, ForSomeVersion10X, SomeHeader, SomeRoot, SomeWriter, SomeWriterV10X, Version, Version10X, UnsupportedVersion};
+use crate::v001::WriterV001;
+use crate::v10x::{ToArchiveBitFlags, WriterV10X};
+use crate::write;
+
+
+#[derive(Debug, Error)]
+#[error("{0} has no name")]
+pub struct MissingName(pub EntryId);
+
+#[derive(Debug, Error)]
+#[error("Unknown flag {0}")]
+pub struct UnknownFlag(pub String);
+
+/// Describes how to build an archive.
+/// Lists the directories and files of the archive in order, together with the
+/// version, the archive flags, the file flags and the compression of every file.
+///
+/// Manifests are written by `bsa extract --manifest` to rebuild an archive from the
+/// extracted files, or written by hand to build archives from scattered sources.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: Version,
+    #[serde(default)]
+    pub archive_flags: Flags,
+    #[serde(default)]
+    pub file_flags: Flags,
+    pub dirs: Vec<ManifestDir>,
+}
+
+/// Flags either given as their raw bits or by their names like `IncludeFileNames`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Flags {
+    Bits(u32),
+    Names(Vec<String>),
+}
+impl Flags {
+    fn names<F: BitFlag + fmt::Debug>(flags: BitFlags<F>) -> Self {
+        Flags::Names(flags.iter()
+            .map(|flag| format!("{:?}", flag))
+            .collect())
+    }
+
+    fn to_bit_flags<F, B>(&self, from_bits: B) -> io::Result<BitFlags<F>>
+    where
+        F: BitFlag + fmt::Debug,
+        B: FnOnce(u32) -> BitFlags<F>,
+    {
+        match self {
+            Flags::Bits(bits) => Ok(from_bits(*bits)),
+            Flags::Names(names) => names.iter()
+                .map(|name| BitFlags::<F>::all().iter()
+                    .find(|flag| format!("{:?}", flag).eq_ignore_ascii_case(name))
+                    .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, UnknownFlag(name.clone()))))
+                .collect(),
+        }
+    }
+}
+impl Default for Flags {
+    fn default() -> Self {
+        Flags::Bits(0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestDir {
+    pub name: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub name: String,
+    /// Where to read the data of the file from. Relative paths are resolved against the root
+    /// directory passed to [`Manifest::dirs`].
+    /// If absent the data is read from `<root>/<dir name>/<file name>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// Overrides whether the file is compressed. If absent the archive default is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed: Option<bool>,
+}
+
+impl Manifest {
+    /// Describe an archive by its header and its content.
+    /// All directories and files need a name.
+    pub fn new(header: &SomeHeader, root: &SomeRoot) -> io::Result<Self> {
+        let (archive_flags, file_flags) = match header {
+            ForSomeVersion::V001(_) => (Flags::default(), Flags::default()),
+            ForSomeVersion::V10X(ForSomeVersion10X::V103(h)) => (Flags::names(h.archive_flags), Flags::names(h.file_flags)),
+            ForSomeVersion::V10X(ForSomeVersion10X::V104(h)) => (Flags::names(h.archive_flags), Flags::names(h.file_flags)),
+            ForSomeVersion::V10X(ForSomeVersion10X::V105(h)) => (Flags::names(h.archive_flags), Flags::names(h.file_flags)),
+        };
+        let dirs = match root {
+            SomeRoot::V001(files) => {
+                let mut dirs: Vec<ManifestDir> = Vec::new();
+                for file in files {
+                    let path = name(&file.id)?;
+                    let (dir_name, file_name) = path.rsplit_once('\\')
+                        .unwrap_or(("", path));
+                    let file = ManifestFile { name: file_name.to_owned(), source: None, compressed: None };
+                    match dirs.iter_mut().find(|dir| dir.name == dir_name) {
+                        Some(dir) => dir.files.push(file),
+                        None => dirs.push(ManifestDir { name: dir_name.to_owned(), files: vec![file] }),
+                    }
+                }
+                dirs
+            },
+            SomeRoot::V10X(dirs) => dirs.iter()
+                .map(|dir| Ok(ManifestDir {
+                    name: name(&dir.id)?.to_owned(),
+                    files: dir.files.iter()
+                        .map(|file| Ok(ManifestFile {
+                            name: name(&file.id)?.to_owned(),
+                            source: None,
+                            compressed: Some(file.compressed),
+                        }))
+                        .collect::<io::Result<_>>()?,
+                }))
+                .collect::<io::Result<_>>()?,
+        };
+        Ok(Self {
+            version: header.version(),
+            archive_flags,
+            file_flags,
+            dirs,
+        })
+    }
+
+    /// A writer for the version and flags of the manifest encoding names with the given codepage.
+    pub fn writer(&self, codepage: Codepage) -> io::Result<SomeWriter> {
+        Ok(match self.version {
+            Version::V001 => SomeWriter::V001(WriterV001 { codepage }),
+            Version::V10X(Version10X::V103) => SomeWriter::V10X(SomeWriterV10X::V103(self.writer_v10x(codepage)?)),
+            Version::V10X(Version10X::V104) => SomeWriter::V10X(SomeWriterV10X::V104(self.writer_v10x(codepage)?)),
+            Version::V10X(Version10X::V105) => SomeWriter::V10X(SomeWriterV10X::V105(self.writer_v10x(codepage)?)),
+            v => return Err(io::Error::new(ErrorKind::Unsupported, UnsupportedVersion(v))),
+        })
+    }
+
+    fn writer_v10x<T, C, AF, RDR>(&self, codepage: Codepage) -> io::Result<WriterV10X<T, C, AF, RDR>>
+    where
+        T: crate::v10x::Versioned,
+        C: crate::compress::Compression,
+        AF: ToArchiveBitFlags,
+        RDR: From<crate::v10x::DirRecord> + Into<crate::v10x::DirRecord> + crate::v10x::SwapBytes + crate::bin::Writable + Sized + Copy,
+    {
+        let mut writer = WriterV10X::new([], []);
+        writer.archive_flags = self.archive_flags.to_bit_flags(AF::to_archive_bit_flags)?;
+        writer.file_flags = self.file_flags.to_bit_flags(|bits| BitFlags::from_bits_truncate(bits as u16))?;
+        writer.codepage = codepage;
+        Ok(writer)
+    }
+
+    /// The directories to write.
+    /// Data is read from the `source` of each file or from where it has been extracted to below `root`.
+    pub fn dirs<P: AsRef<Path>>(&self, root: P) -> Vec<write::Dir<PathBuf>> {
+        let root = root.as_ref();
+        self.dirs.iter()
+            .map(|dir| {
+                let dir_path = join(root.to_path_buf(), &dir.name);
+                write::Dir {
+                    name: dir.name.clone(),
+                    files: dir.files.iter()
+                        .map(|file| write::File {
+                            name: file.name.clone(),
+                            compressed: file.compressed,
+                            data: match &file.source {
+                                Some(source) => root.join(source),
+                                None => join(dir_path.clone(), &file.name),
+                            },
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn name(id: &EntryId) -> io::Result<&str> {
+    id.name.as_deref()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, MissingName(id.clone())))
+}
+
+fn join(mut path: PathBuf, name: &str) -> PathBuf {
+    for part in name.split(|c| c == '\\' || c == '/').filter(|part| !part.is_empty()) {
+        path.push(part);
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+    use crate::{FileFlag, Reader, SomeReader, Writer, WriterV105, ArchiveFlagV105};
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    #[test]
+    fn rebuild_from_extracted_files() {
+        let mut dirs = vec![
+            Dir::new("b", [File::new("y", vec![1, 2, 3]), File::new("x", vec![4])]),
+            Dir::new("a", [File::new("z", vec![5, 6])]),
+        ];
+        dirs[0].files[1].compressed = Some(true);
+        let writer = WriterV105::new([ArchiveFlagV105::IncludeDirectoryNames, ArchiveFlagV105::IncludeFileNames], []);
+        let original = bsa_bytes(writer, dirs.clone()).into_inner();
+
+        let mut bsa = SomeReader::read_bsa(Cursor::new(original.clone()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let root = bsa.list()
+            .unwrap_or_else(|err| panic!("could not read dirs {}", err));
+        let manifest = Manifest::new(&bsa.header(), &root)
+            .unwrap_or_else(|err| panic!("could not create manifest {}", err));
+
+        let extracted = temp_dir("bsa-manifest-rebuild");
+        for dir in &dirs {
+            for file in &dir.files {
+                let path = extracted.join(&dir.name).join(&file.name);
+                fs::create_dir_all(path.parent().unwrap_or(&extracted))
+                    .unwrap_or_else(|err| panic!("could not create dir {}", err));
+                fs::write(path, &file.data)
+                    .unwrap_or_else(|err| panic!("could not write file {}", err));
+            }
+        }
+
+        let mut rebuilt = Cursor::new(Vec::<u8>::new());
+        let res = manifest.writer(Codepage::default())
+            .unwrap_or_else(|err| panic!("could not create writer {}", err))
+            .write_bsa(manifest.dirs(&extracted), &mut rebuilt);
+        fs::remove_dir_all(&extracted)
+            .unwrap_or_else(|err| panic!("could not remove dir {}", err));
+        res.unwrap_or_else(|err| panic!("could not write bsa {}", err));
+
+        assert_eq!(manifest.dirs[0].name, "b", "order of dirs");
+        assert_eq!(rebuilt.into_inner(), original, "rebuilt bytes");
+    }
+    #[test]
+    fn build_from_scattered_sources() {
+        let sources = temp_dir("bsa-manifest-sources");
+        fs::create_dir_all(&sources)
+            .unwrap_or_else(|err| panic!("could not create dir {}", err));
+        fs::write(sources.join("iron_final.nif"), [1, 2, 3])
+            .unwrap_or_else(|err| panic!("could not write file {}", err));
+
+        let manifest = Manifest {
+            version: Version::V10X(Version10X::V105),
+            archive_flags: Flags::Names(vec!["IncludeDirectoryNames".to_owned(), "includefilenames".to_owned()]),
+            file_flags: Flags::Names(vec!["Meshes".to_owned()]),
+            dirs: vec![ManifestDir {
+                name: "meshes\\armor".to_owned(),
+                files: vec![ManifestFile {
+                    name: "iron.nif".to_owned(),
+                    source: Some(PathBuf::from("iron_final.nif")),
+                    compressed: Some(true),
+                }],
+            }],
+        };
+        let mut out = Cursor::new(Vec::<u8>::new());
+        let res = manifest.writer(Codepage::default())
+            .unwrap_or_else(|err| panic!("could not create writer {}", err))
+            .write_bsa(write::Dir::from_manifest(&manifest, &sources), &mut out);
+        fs::remove_dir_all(&sources)
+            .unwrap_or_else(|err| panic!("could not remove dir {}", err));
+        res.unwrap_or_else(|err| panic!("could not write bsa {}", err));
+
+        let mut bsa = SomeReader::read_bsa(Cursor::new(out.into_inner()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let root = bsa.list()
+            .unwrap_or_else(|err| panic!("could not read dirs {}", err));
+        let read = Manifest::new(&bsa.header(), &root)
+            .unwrap_or_else(|err| panic!("could not create manifest {}", err));
+        assert_eq!(read.archive_flags, Flags::Names(vec!["IncludeDirectoryNames".to_owned(), "IncludeFileNames".to_owned()]), "archive_flags");
+        assert_eq!(read.file_flags, Flags::Names(vec!["Meshes".to_owned()]), "file_flags");
+        assert_eq!(read.dirs[0].files[0].compressed, Some(true), "compressed");
+    }
+
+    #[test]
+    fn unknown_flag() {
+        let flags = Flags::Names(vec!["NoSuchFlag".to_owned()]);
+        assert!(flags.to_bit_flags::<FileFlag, _>(|bits| BitFlags::from_bits_truncate(bits as u16)).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Result};
+
+use crate::{Codepage, Hash, SomeRoot};
+use crate::read;
+
+
+/// A dictionary of candidate paths used to recover the names of directories
+/// and files in archives that don't include them.
+/// The file format permits archives without names, in which case [`read::EntryId`]
+/// only contains a [`Hash`]. Names can be recovered by hashing candidate paths and
+/// looking up the hashes of the entries.
+///
+/// Candidates can come from wordlists, from other archives or from plugins
+/// referencing assets.
+#[derive(Debug, Clone, Default)]
+pub struct Names {
+    dirs_v10x: HashMap<Hash, String>,
+    files_v10x: HashMap<Hash, String>,
+    paths_v001: HashMap<Hash, String>,
+    codepage: Codepage,
+}
+impl Names {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty dictionary that hashes names encoded with the given codepage.
+    /// Candidates that can't be represented in the codepage are ignored.
+    pub fn with_codepage(codepage: Codepage) -> Self {
+        Self {
+            codepage,
+            ..Self::default()
+        }
+    }
+
+    /// The number of distinct candidate paths.
+    pub fn len(&self) -> usize {
+        self.paths_v001.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths_v001.is_empty()
+    }
+
+    /// Add the complete path of a file or directory.
+    /// Every parent directory of the path is added as well.
+    pub fn insert<S: AsRef<str>>(&mut self, path: S) {
+        let path = sanitize(path.as_ref());
+        if path.is_empty() {
+            return;
+        }
+        let codepage = self.codepage;
+        if codepage.encode(&path).is_err() {
+            return;
+        }
+        let hash_v10x = |name: &str| codepage.hash_v10x(name).unwrap_or_else(|_| Hash::v10x(name));
+        for (i, c) in path.char_indices() {
+            if c == '\\' {
+                insert_hashed(&mut self.dirs_v10x, hash_v10x(&path[..i]), &path[..i]);
+            }
+        }
+        insert_hashed(&mut self.dirs_v10x, hash_v10x(&path), &path);
+        let file_name = path.rsplit('\\').next().unwrap_or(&path);
+        insert_hashed(&mut self.files_v10x, hash_v10x(file_name), file_name);
+        let hash_v001 = codepage.hash_v001(&path).unwrap_or_else(|_| Hash::v001(&path));
+        insert_hashed(&mut self.paths_v001, hash_v001, &path);
+    }
+
+    /// Read a wordlist containing one path per line.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn read_list<R: BufRead>(&mut self, reader: R) -> Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.insert(line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Scan binary data, like plugin files, for null terminated strings that look like
+    /// asset paths.
+    /// Plugins reference meshes and textures relative to their root directory, so
+    /// these are added with and without the root directory.
+    pub fn harvest<R: Read>(&mut self, mut reader: R) -> Result<()> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        for chunk in bytes.split(|b| *b == 0) {
+            let candidate = chunk.iter()
+                .rposition(|b| !is_path_char(*b))
+                .map_or(chunk, |i| &chunk[i + 1..]);
+            if let Some(path) = as_asset_path(candidate) {
+                if let Some(root) = asset_root(path) {
+                    self.insert(format!("{}\\{}", root, path));
+                }
+                self.insert(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the names of all named entries of an archive.
+    pub fn harvest_root(&mut self, root: &SomeRoot) {
+        match root {
+            SomeRoot::V001(files) => {
+                for name in files.iter().filter_map(|file| file.id.name.as_ref()) {
+                    self.insert(name);
+                }
+            },
+            SomeRoot::V10X(dirs) => {
+                for dir in dirs {
+                    if let Some(dir_name) = &dir.id.name {
+                        self.insert(dir_name);
+                        for name in dir.files.iter().filter_map(|file| file.id.name.as_ref()) {
+                            self.insert(format!("{}\\{}", dir_name, name));
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    /// Fill in the names of entries that don't have one.
+    /// The result is the number of names that have been recovered.
+    pub fn recover(&self, root: &mut SomeRoot) -> usize {
+        match root {
+            SomeRoot::V001(files) => self.recover_v001(files),
+            SomeRoot::V10X(dirs) => self.recover_v10x(dirs),
+        }
+    }
+
+    /// Fill in the names of v001 files that don't have one.
+    pub fn recover_v001(&self, files: &mut [read::File]) -> usize {
+        files.iter_mut()
+            .map(|file| recover_id(&self.paths_v001, &mut file.id))
+            .filter(|recovered| *recovered)
+            .count()
+    }
+
+    /// Fill in the names of v10x directories and files that don't have one.
+    pub fn recover_v10x(&self, dirs: &mut [read::Dir]) -> usize {
+        let mut count = 0;
+        for dir in dirs {
+            if recover_id(&self.dirs_v10x, &mut dir.id) {
+                count += 1;
+            }
+            for file in &mut dir.files {
+                if recover_id(&self.files_v10x, &mut file.id) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+impl<S: AsRef<str>> Extend<S> for Names {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, paths: I) {
+        for path in paths {
+            self.insert(path);
+        }
+    }
+}
+impl<S: AsRef<str>> std::iter::FromIterator<S> for Names {
+    fn from_iter<I: IntoIterator<Item = S>>(paths: I) -> Self {
+        let mut names = Names::new();
+        names.extend(paths);
+        names
+    }
+}
+
+fn sanitize(path: &str) -> String {
+    path.to_ascii_lowercase()
+        .replace('/', "\\")
+        .trim_matches('\\')
+        .to_owned()
+}
+
+fn insert_hashed(names: &mut HashMap<Hash, String>, hash: Hash, name: &str) {
+    names.entry(hash)
+        .or_insert_with(|| name.to_owned());
+}
+
+fn recover_id(names: &HashMap<Hash, String>, id: &mut read::EntryId) -> bool {
+    if id.name.is_some() {
+        return false;
+    }
+    id.name = names.get(&id.hash).cloned();
+    id.name.is_some()
+}
+
+fn is_path_char(b: u8) -> bool {
+    b.is_ascii_graphic() || b == b' '
+}
+
+fn as_asset_path(bytes: &[u8]) -> Option<&str> {
+    let path = std::str::from_utf8(bytes).ok()?.trim();
+    let (stem, ext) = path.rsplit_once('.')?;
+    let is_ext = (2..=4).contains(&ext.len()) && ext.bytes().all(|b| b.is_ascii_alphanumeric());
+    if is_ext && !stem.is_empty() && !path.contains(':') {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn asset_root(path: &str) -> Option<&'static str> {
+    let ext = path.rsplit('.').next()?.to_lowercase();
+    match ext.as_str() {
+        "nif" | "kf" | "hkx" | "tri" => Some("meshes"),
+        "dds" => Some("textures"),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::{SomeRoot, WriterV105};
+    use crate::read::Reader;
+    use crate::v105::ReaderV105;
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    #[test]
+    fn insert_adds_parents() {
+        let mut names = Names::new();
+        names.insert("Meshes/Armor/iron.nif");
+
+        assert_eq!(names.dirs_v10x.get(&Hash::v10x("meshes")), Some(&"meshes".to_owned()));
+        assert_eq!(names.dirs_v10x.get(&Hash::v10x("meshes\\armor")), Some(&"meshes\\armor".to_owned()));
+        assert_eq!(names.files_v10x.get(&Hash::v10x("iron.nif")), Some(&"iron.nif".to_owned()));
+        assert_eq!(names.paths_v001.get(&Hash::v001("meshes\\armor\\iron.nif")), Some(&"meshes\\armor\\iron.nif".to_owned()));
+    }
+
+    #[test]
+    fn harvest_finds_asset_paths() {
+        let bytes = b"EDID\x05\x00Iron\x00MODL\x10\x00Armor\\Iron\\a.nif\x00".to_vec();
+        let mut names = Names::new();
+        names.harvest(Cursor::new(bytes))
+            .unwrap_or_else(|err| panic!("could not harvest names {}", err));
+
+        assert!(names.dirs_v10x.contains_key(&Hash::v10x("meshes\\armor\\iron")), "dir with root");
+        assert!(names.dirs_v10x.contains_key(&Hash::v10x("armor\\iron")), "dir without root");
+        assert!(names.files_v10x.contains_key(&Hash::v10x("a.nif")), "file");
+    }
+
+    #[test]
+    fn recover_nameless_v105() {
+        let dirs = vec![
+            Dir::new("a\\b", [
+                File::new("c.dds", vec![1, 2, 3]),
+                File::new("d.dds", vec![4]),
+            ]),
+        ];
+        let bytes = bsa_bytes(WriterV105::new([], []), dirs);
+        let mut bsa = ReaderV105::read_bsa(bytes)
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let mut root = SomeRoot::V10X(bsa.list()
+            .unwrap_or_else(|err| panic!("could not read dirs {}", err)));
+
+        let names: Names = ["a/b/c.dds"].iter().collect();
+        assert_eq!(names.recover(&mut root), 2, "recovered names");
+
+        if let SomeRoot::V10X(dirs) = root {
+            assert_eq!(dirs[0].id.name, Some("a\\b".to_owned()), "dirs[0].id.name");
+            assert_eq!(dirs[0].files[0].id.name, Some("c.dds".to_owned()), "dirs[0].files[0].id.name");
+            assert_eq!(dirs[0].files[1].id.name, None, "dirs[0].files[1].id.name");
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+use crate::tree::Totals;
+
+
+#[derive(Debug, Error)]
+#[error("cancelled")]
+pub struct Cancelled;
+impl Cancelled {
+    /// Whether an error was caused by cancelling an operation.
+    pub fn is_cause_of(err: &io::Error) -> bool {
+        err.get_ref().map_or(false, |inner| inner.is::<Cancelled>())
+    }
+}
+
+/// Gets notified about the progress of writing or extracting files and may cancel it.
+/// All methods do nothing by default.
+///
+/// For each file [`file_started`](Observer::file_started) is called first,
+/// followed by any number of calls to [`bytes`](Observer::bytes) and finally
+/// [`file_finished`](Observer::file_finished).
+pub trait Observer {
+    /// Called once before the first file with the number of files and their total size.
+    /// Sizes of sources that don't know their size are not included.
+    fn started(&mut self, _totals: Totals) {}
+
+    fn file_started(&mut self, _path: &str, _size: Option<u64>) {}
+
+    /// Called with the number of bytes processed since the last call.
+    fn bytes(&mut self, _bytes: u64) {}
+
+    fn file_finished(&mut self, _path: &str) {}
+
+    /// Checked before each file and each chunk of data.
+    /// Once this returns `true` the operation stops with a [`Cancelled`] error.
+    /// It is not of kind [`ErrorKind::Interrupted`], since that causes most readers and writers to retry.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+impl Observer for () {}
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn started(&mut self, totals: Totals) {
+        (**self).started(totals)
+    }
+    fn file_started(&mut self, path: &str, size: Option<u64>) {
+        (**self).file_started(path, size)
+    }
+    fn bytes(&mut self, bytes: u64) {
+        (**self).bytes(bytes)
+    }
+    fn file_finished(&mut self, path: &str) {
+        (**self).file_finished(path)
+    }
+    fn is_cancelled(&self) -> bool {
+        (**self).is_cancelled()
+    }
+}
+
+/// A flag that can be shared between threads to cancel an operation.
+/// On its own it is an [`Observer`] that only cancels,
+/// other observers can delegate [`is_cancelled`](Observer::is_cancelled) to it.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+impl Observer for CancelToken {
+    fn is_cancelled(&self) -> bool {
+        CancelToken::is_cancelled(self)
+    }
+}
+
+pub(crate) fn check_cancelled(observer: &dyn Observer) -> io::Result<()> {
+    if observer.is_cancelled() {
+        Err(io::Error::new(ErrorKind::Other, Cancelled))
+    } else {
+        Ok(())
+    }
+}
+
+/// Reports the bytes read from the inner reader.
+pub(crate) struct ObservedRead<'a, R> {
+    pub inner: R,
+    pub observer: &'a mut dyn Observer,
+}
+impl<'a, R: Read> Read for ObservedRead<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        check_cancelled(self.observer)?;
+        let n = self.inner.read(buf)?;
+        self.observer.bytes(n as u64);
+        Ok(n)
+    }
+}
+
+/// Reports the bytes written to the inner writer, up to `remaining` bytes.
+pub(crate) struct ObservedWrite<'a, W> {
+    pub inner: W,
+    pub observer: &'a mut dyn Observer,
+    pub remaining: u64,
+}
+impl<'a, W: Write> Write for ObservedWrite<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        check_cancelled(self.observer)?;
+        let n = self.inner.write(buf)?;
+        let reported = self.remaining.min(n as u64);
+        if reported > 0 {
+            self.remaining -= reported;
+            self.observer.bytes(reported);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::{Reader, Writer, WriterV105};
+    use crate::v105::ReaderV105;
+    use crate::write::{Dir, File};
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+        bytes: u64,
+        cancel_after: Option<usize>,
+    }
+    impl Observer for Recorder {
+        fn started(&mut self, totals: Totals) {
+            self.events.push(format!("started {} {}", totals.files, totals.size));
+        }
+        fn file_started(&mut self, path: &str, _size: Option<u64>) {
+            self.events.push(format!("start {}", path));
+        }
+        fn bytes(&mut self, bytes: u64) {
+            self.bytes += bytes;
+        }
+        fn file_finished(&mut self, path: &str) {
+            self.events.push(format!("finish {}", path));
+        }
+        fn is_cancelled(&self) -> bool {
+            self.cancel_after.map_or(false, |n| self.events.len() >= n)
+        }
+    }
+
+    fn some_dirs() -> Vec<Dir<Vec<u8>>> {
+        vec![Dir::new("a", [File::new("b", vec![1, 2, 3]), File::new("c", vec![4; 10])])]
+    }
+
+    #[test]
+    fn reports_written_files() {
+        let mut recorder = Recorder::default();
+        let mut out = Cursor::new(Vec::<u8>::new());
+        WriterV105::default().write_bsa_observed(some_dirs(), &mut out, &mut recorder)
+            .unwrap_or_else(|err| panic!("could not write bsa {}", err));
+
+        assert_eq!(recorder.events, vec!["started 2 13", "start a\\b", "finish a\\b", "start a\\c", "finish a\\c"]);
+        assert_eq!(recorder.bytes, 13, "bytes");
+
+        let mut bsa = ReaderV105::read_bsa(Cursor::new(out.into_inner()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let dirs = bsa.list()
+            .unwrap_or_else(|err| panic!("could not read dirs {}", err));
+        let mut recorder = Recorder::default();
+        bsa.extract_observed(&dirs[0].files[1], Vec::new(), &mut recorder)
+            .unwrap_or_else(|err| panic!("could not extract file {}", err));
+        assert_eq!(recorder.bytes, 10, "extracted bytes");
+    }
+
+    #[test]
+    fn cancels_writing() {
+        let mut recorder = Recorder { cancel_after: Some(2), ..Recorder::default() };
+        let err = WriterV105::default()
+            .write_bsa_observed(some_dirs(), Cursor::new(Vec::<u8>::new()), &mut recorder)
+            .err()
+            .unwrap_or_else(|| panic!("writing was not cancelled"));
+
+        assert!(Cancelled::is_cause_of(&err), "cancelled");
+        assert_eq!(recorder.events, vec!["started 2 13", "start a\\b"]);
+    }
+}
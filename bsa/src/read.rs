@@ -1,11 +1,14 @@
 use std::slice::{Iter, SliceIndex};
 use std::path::Path;
 use std::ops::Index;
-use std::io::{BufReader, Result, Write};
+use std::io::{BufReader, Cursor, Result, Write};
 use std::fs;
 use std::fmt;
+use std::rc::Rc;
+use std::cell::RefCell;
 
-use crate::Hash;
+use crate::{Codepage, DataSource, Hash};
+use crate::progress::{Observer, ObservedWrite, check_cancelled};
 
 
 /// Identifier for [`Dir`] and [`File`].
@@ -15,6 +18,7 @@ use crate::Hash;
 /// Games may refuse to load archives without names.
 /// The file format itself permits directories and files without names though.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EntryId {
     pub hash: Hash,
     pub name: Option<String>,
@@ -34,6 +38,7 @@ impl fmt::Display for EntryId {
 /// Subdirectories are represented by differend entries.
 /// No official bsa archives contain empty directories, this isn't enforced by the file format though.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dir {
     pub id: EntryId,
     pub files: Vec<File>,
@@ -56,12 +61,99 @@ impl<I: SliceIndex<[File]>> Index<I> for Dir {
 
 /// A file inside of a bsa archive.
 /// If the file is [`compressed`] then [`size`] referes to the compressed data size.
+/// If the archive embeds file names in the data blocks, [`embedded_name`] contains the
+/// complete path stored there. It is independent of the name table of the archive.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct File {
     pub id: EntryId,
     pub compressed: bool,
     pub offset: u64,
     pub size: usize,
+    pub embedded_name: Option<String>,
+}
+
+/// The complete path of a file as printed by `list`, using `/` as separator.
+/// Files without a name are represented by their hash like `#0123456789abcdef`.
+pub fn path(dir: Option<&Dir>, file: &File) -> String {
+    match dir {
+        Some(dir) => format!("{}/{}", dir.id, file.id),
+        None => file.id.to_string(),
+    }
+}
+
+/// Iterator over the files of an archive returned by [`Reader::entries`].
+/// Files of archives without directories don't have a [`Dir`].
+pub enum Entries<'a> {
+    Files(Iter<'a, File>),
+    Dirs(Iter<'a, Dir>, Option<(&'a Dir, Iter<'a, File>)>),
+}
+impl<'a> Entries<'a> {
+    pub fn files(files: &'a [File]) -> Self {
+        Entries::Files(files.iter())
+    }
+
+    pub fn dirs(dirs: &'a [Dir]) -> Self {
+        Entries::Dirs(dirs.iter(), None)
+    }
+}
+impl<'a> Iterator for Entries<'a> {
+    type Item = (Option<&'a Dir>, &'a File);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Entries::Files(files) => files.next().map(|file| (None, file)),
+            Entries::Dirs(dirs, current) => loop {
+                if let Some((dir, files)) = current {
+                    if let Some(file) = files.next() {
+                        return Some((Some(*dir), file));
+                    }
+                }
+                let dir = dirs.next()?;
+                *current = Some((dir, dir.files.iter()));
+            },
+        }
+    }
+}
+
+/// A file of an open archive as data to write into another archive.
+/// The reader is shared by all files of the archive, see [`write::Dir::from_archive`](crate::write::Dir::from_archive).
+/// Opening it extracts the file into memory.
+#[derive(Debug)]
+pub struct ArchiveEntry<R> {
+    pub reader: Rc<RefCell<R>>,
+    pub file: File,
+}
+impl<R> ArchiveEntry<R> {
+    pub fn new(reader: &Rc<RefCell<R>>, file: File) -> Self {
+        Self {
+            reader: Rc::clone(reader),
+            file,
+        }
+    }
+}
+impl<R> Clone for ArchiveEntry<R> {
+    fn clone(&self) -> Self {
+        Self::new(&self.reader, self.file.clone())
+    }
+}
+impl<R: Reader> DataSource for ArchiveEntry<R> {
+    type Read = Cursor<Vec<u8>>;
+
+    fn open(&self) -> Result<Self::Read> {
+        let mut data = Vec::with_capacity(self.file.size);
+        self.reader.borrow_mut().extract(&self.file, &mut data)?;
+        Ok(Cursor::new(data))
+    }
+
+    /// Only known for uncompressed files without an embedded name.
+    fn size(&self) -> Option<u64> {
+        if self.file.compressed || self.file.embedded_name.is_some() {
+            None
+        } else {
+            Some(self.file.size as u64)
+        }
+    }
 }
 
 /// Open a bsa archive.
@@ -83,7 +175,132 @@ pub trait Reader: Sized {
 
     fn header(&self) -> Self::Header;
 
+    /// The codepage names are encoded with.
+    fn codepage(&self) -> Codepage;
+
+    /// Set the codepage names are encoded with.
+    /// Defaults to [`Codepage::WINDOWS_1252`].
+    fn set_codepage(&mut self, codepage: Codepage);
+
+    fn with_codepage(mut self, codepage: Codepage) -> Self {
+        self.set_codepage(codepage);
+        self
+    }
+
+    /// Number of bytes from the start of the archive to the end of its index,
+    /// which contains everything [`list`](Self::list) reads except names embedded in data blocks.
+    fn index_size(&self) -> u64;
+
+    /// Iterate over all files together with their directory.
+    /// The index of the archive is read once and borrowed from afterwards.
+    fn entries(&mut self) -> Result<Entries<'_>>;
+
+    /// Clone of the complete index of the archive.
     fn list(&mut self) -> Result<Self::Root>;
 
+    /// Find a file by its complete path, as returned by [`path`], ignoring case.
+    /// Both `/` and `\` are accepted as separator.
+    /// Directories and files without a name are matched by the hash of the path.
+    fn find(&mut self, path: &str) -> Result<Option<File>> {
+        let path = path.replace('/', "\\");
+        let path = path.trim_matches('\\');
+        let (dir_name, file_name) = match path.rfind('\\') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => ("", path),
+        };
+        let codepage = self.codepage();
+        let path = (path.to_lowercase(), codepage.hash_v001(path).ok());
+        let dir = (dir_name.to_lowercase(), codepage.hash_v10x(dir_name).ok());
+        let file = (file_name.to_lowercase(), codepage.hash_v10x(file_name).ok());
+        let matches = |id: &EntryId, (name, hash): &(String, Option<Hash>)| match &id.name {
+            Some(id_name) => id_name.replace('/', "\\").trim_matches('\\').to_lowercase() == *name,
+            None => Some(id.hash) == *hash,
+        };
+        Ok(self.entries()?
+            .find(|(d, f)| match d {
+                None => matches(&f.id, &path),
+                Some(d) => matches(&d.id, &dir) && matches(&f.id, &file),
+            })
+            .map(|(_, f)| f.clone()))
+    }
+
     fn extract<W: Write>(&mut self, file: &File, writer: W) -> Result<()>;
+
+    /// The size of a file once extracted, without extracting it.
+    fn uncompressed_size(&mut self, file: &File) -> Result<u64>;
+
+    /// Extract a file while reporting the progress to an observer, that may also cancel extraction.
+    /// Progress is reported in bytes of the archive, so bytes of compressed files
+    /// are reported as they are written until the stored size is reached.
+    fn extract_observed<W: Write>(&mut self, file: &File, writer: W, observer: &mut dyn Observer) -> Result<()> {
+        check_cancelled(observer)?;
+        let path = file.id.to_string();
+        observer.file_started(&path, Some(file.size as u64));
+        let mut out = ObservedWrite {
+            inner: writer,
+            observer: &mut *observer,
+            remaining: file.size as u64,
+        };
+        self.extract(file, &mut out)?;
+        let remaining = out.remaining;
+        if remaining > 0 {
+            observer.bytes(remaining);
+        }
+        observer.file_finished(&path);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{ArchiveFlagV105, SomeReader, WriterV001, WriterV105};
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    #[test]
+    fn find_ignores_case_and_separator() {
+        let dirs = || vec![Dir::new("Meshes\\Clutter", [File::new("Cup.nif", vec![1]), File::new("plate.nif", vec![2])])];
+        for bytes in [bsa_bytes(WriterV105::default(), dirs()), bsa_bytes(WriterV001::default(), dirs())] {
+            let mut bsa = SomeReader::read_bsa(bytes)
+                .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+            for path in &["meshes/clutter/cup.nif", "MESHES\\Clutter\\Cup.NIF", "/meshes/clutter/cup.nif"] {
+                let file = bsa.find(path)
+                    .unwrap_or_else(|err| panic!("could not find {} {}", path, err))
+                    .unwrap_or_else(|| panic!("{} not found", path));
+                let mut data = Vec::new();
+                bsa.extract(&file, &mut data)
+                    .unwrap_or_else(|err| panic!("could not extract {} {}", path, err));
+                assert_eq!(data, vec![1], "data of {}", path);
+            }
+            let missing = bsa.find("meshes/cup.nif")
+                .unwrap_or_else(|err| panic!("could not find file {}", err));
+            assert!(missing.is_none(), "found missing file");
+        }
+    }
+
+    #[test]
+    fn find_without_names_by_hash() {
+        let dirs = vec![
+            Dir::new("", [File::new("readme.txt", vec![3])]),
+            Dir::new("textures", [File::new("a.dds", vec![1]), File::new("b.dds", vec![2])]),
+        ];
+        let mut writer = WriterV105::default();
+        writer.archive_flags.remove(ArchiveFlagV105::IncludeDirectoryNames | ArchiveFlagV105::IncludeFileNames);
+        let mut bsa = SomeReader::read_bsa(bsa_bytes(writer, dirs))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        for (path, expected) in &[("Textures/A.dds", 1), ("textures\\b.dds", 2), ("readme.txt", 3)] {
+            let file = bsa.find(path)
+                .unwrap_or_else(|err| panic!("could not find {} {}", path, err))
+                .unwrap_or_else(|| panic!("{} not found", path));
+            assert_eq!(file.id.name, None, "name of {}", path);
+            let mut data = Vec::new();
+            bsa.extract(&file, &mut data)
+                .unwrap_or_else(|err| panic!("could not extract {} {}", path, err));
+            assert_eq!(data, vec![*expected], "data of {}", path);
+        }
+        let missing = bsa.find("textures/c.dds")
+            .unwrap_or_else(|err| panic!("could not find file {}", err));
+        assert!(missing.is_none(), "found missing file");
+    }
 }
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use glob::{MatchOptions, Pattern};
+use thiserror::Error;
+
+use crate::write::{Dir, File};
+
+
+#[derive(Debug, Error)]
+#[error("{0} is not valid unicode")]
+pub struct NonUnicodeName(pub PathBuf);
+
+#[derive(Debug, Error)]
+#[error("{0} is a symbolic link")]
+pub struct SymlinkNotAllowed(pub PathBuf);
+
+/// What to do with symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Add the file or directory the link points to.
+    Follow,
+    /// Ignore links.
+    Skip,
+    /// Fail on the first link.
+    Error,
+}
+impl Default for Symlinks {
+    fn default() -> Self {
+        Symlinks::Follow
+    }
+}
+
+/// Collects the files below a directory to write them to an archive.
+/// Directory and file names are relative to the scanned directory and use `\` as separator.
+/// Files directly inside the scanned directory are put into the directory with the empty name.
+/// Directories are ordered by their name and files by their name inside each directory,
+/// ignoring case, so the same directory always results in the same archive.
+#[derive(Debug, Clone, Default)]
+pub struct Scanner {
+    /// Glob patterns for files to include. If empty all files are included.
+    /// Patterns are matched against the path relative to the scanned directory using `/` as separator.
+    pub include: Vec<Pattern>,
+    /// Glob patterns for files and directories to exclude.
+    pub exclude: Vec<Pattern>,
+    /// Include files and directories starting with a `.` like `.git`.
+    pub hidden: bool,
+    /// Include editor backups like `foo~`, `foo.bak` or `#foo#`.
+    pub backups: bool,
+    pub symlinks: Symlinks,
+}
+impl Scanner {
+    const MATCH_OPTS: MatchOptions = MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn scan<P: AsRef<Path>>(&self, root: P) -> io::Result<Vec<Dir<PathBuf>>> {
+        let root = root.as_ref();
+        let mut dirs = BTreeMap::new();
+        let mut visited = HashSet::new();
+        visited.insert(fs::canonicalize(root)?);
+        self.scan_dir(root, &[], &mut visited, &mut dirs)?;
+        Ok(dirs.into_values()
+            .map(|mut dir| {
+                dir.files.sort_by_key(|file: &File<PathBuf>| file.name.to_lowercase());
+                dir
+            })
+            .collect())
+    }
+
+    fn scan_dir(
+        &self,
+        path: &Path,
+        parents: &[String],
+        visited: &mut HashSet<PathBuf>,
+        dirs: &mut BTreeMap<String, Dir<PathBuf>>,
+    ) -> io::Result<()> {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let entry_path = entry.path();
+            let name = entry.file_name()
+                .into_string()
+                .map_err(|_| io::Error::new(ErrorKind::InvalidData, NonUnicodeName(entry_path.clone())))?;
+            if !self.is_candidate(&name) {
+                continue;
+            }
+            let mut components = parents.to_vec();
+            components.push(name);
+            if self.is_excluded(&components) {
+                continue;
+            }
+
+            let mut file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                match self.symlinks {
+                    Symlinks::Skip => continue,
+                    Symlinks::Error => return Err(io::Error::new(ErrorKind::InvalidInput, SymlinkNotAllowed(entry_path))),
+                    Symlinks::Follow => file_type = fs::metadata(&entry_path)?.file_type(),
+                }
+            }
+
+            if file_type.is_dir() {
+                // links may point back to a parent
+                if visited.insert(fs::canonicalize(&entry_path)?) {
+                    self.scan_dir(&entry_path, &components, visited, dirs)?;
+                }
+            } else if self.is_included(&components) {
+                let file_name = components.pop().unwrap_or_default();
+                let dir_name = components.join("\\");
+                dirs.entry(dir_name.to_lowercase())
+                    .or_insert_with(|| Dir::new(dir_name, []))
+                    .files.push(File::new(file_name, entry_path));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_candidate(&self, name: &str) -> bool {
+        let is_hidden = name.starts_with('.');
+        let is_backup = name.ends_with('~')
+            || name.to_lowercase().ends_with(".bak")
+            || (name.len() > 1 && name.starts_with('#') && name.ends_with('#'));
+        (self.hidden || !is_hidden) && (self.backups || !is_backup)
+    }
+
+    fn is_excluded(&self, components: &[String]) -> bool {
+        let path = components.join("/");
+        self.exclude.iter()
+            .any(|pattern| pattern.matches_with(&path, Self::MATCH_OPTS))
+    }
+
+    fn is_included(&self, components: &[String]) -> bool {
+        let path = components.join("/");
+        self.include.is_empty() || self.include.iter()
+            .any(|pattern| pattern.matches_with(&path, Self::MATCH_OPTS))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_tree(name: &str, files: &[&str]) -> PathBuf {
+        let root = crate::write::test::temp_dir(name);
+        if root.exists() {
+            fs::remove_dir_all(&root)
+                .unwrap_or_else(|err| panic!("could not remove dir {}", err));
+        }
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap_or(&root))
+                .unwrap_or_else(|err| panic!("could not create dir {}", err));
+            fs::write(path, [0])
+                .unwrap_or_else(|err| panic!("could not write file {}", err));
+        }
+        root
+    }
+
+    fn names(dirs: &[Dir<PathBuf>]) -> Vec<String> {
+        dirs.iter()
+            .flat_map(|dir| dir.files.iter().map(move |file| format!("{}|{}", dir.name, file.name)))
+            .collect()
+    }
+
+    #[test]
+    fn skips_hidden_and_backups_sorted() {
+        let root = temp_tree("bsa-scan-hidden", &[
+            "b.txt", "a.txt", "a.txt~", ".hidden", ".git/config",
+            "meshes/Z.nif", "meshes/armor/a.nif", "meshes/a.nif.bak",
+        ]);
+        let dirs = Scanner::new().scan(&root);
+        fs::remove_dir_all(&root)
+            .unwrap_or_else(|err| panic!("could not remove dir {}", err));
+        let dirs = dirs.unwrap_or_else(|err| panic!("could not scan dir {}", err));
+
+        assert_eq!(names(&dirs), vec!["|a.txt", "|b.txt", "meshes|Z.nif", "meshes\\armor|a.nif"]);
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let root = temp_tree("bsa-scan-globs", &[
+            "meshes/a.nif", "meshes/a.psd", "textures/a.dds", "source/a.nif",
+        ]);
+        let mut scanner = Scanner::new();
+        scanner.include = vec![Pattern::new("*.nif").unwrap_or_else(|err| panic!("{}", err))];
+        scanner.exclude = vec![Pattern::new("source").unwrap_or_else(|err| panic!("{}", err))];
+        let dirs = scanner.scan(&root);
+        fs::remove_dir_all(&root)
+            .unwrap_or_else(|err| panic!("could not remove dir {}", err));
+        let dirs = dirs.unwrap_or_else(|err| panic!("could not scan dir {}", err));
+
+        assert_eq!(names(&dirs), vec!["meshes|a.nif"]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{Reader, SomeHeader, SomeRoot};
+use crate::checksum::ChecksumEntry;
+use crate::read::{Dir, Entries, File};
+
+
+/// Number of files and their sizes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Group {
+    pub files: usize,
+    /// Size of the files inside the archive.
+    pub stored: u64,
+    pub uncompressed: u64,
+}
+impl Group {
+    /// Stored size relative to the uncompressed size, 1 if there is no data.
+    pub fn ratio(&self) -> f64 {
+        if self.uncompressed == 0 {
+            1.0
+        } else {
+            self.stored as f64 / self.uncompressed as f64
+        }
+    }
+
+    fn add(&mut self, entry: &ChecksumEntry) {
+        self.files += 1;
+        self.stored += entry.size as u64;
+        self.uncompressed += entry.uncompressed_size;
+    }
+}
+
+/// Files with the same data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Duplicates {
+    pub files: Vec<ChecksumEntry>,
+    /// Stored bytes that could be saved by keeping only the smallest copy.
+    pub wasted: u64,
+}
+
+/// Bytes used to store names.
+/// Names are counted in bytes of their UTF-8 encoding, which is their stored size for ASCII names.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct NameTable {
+    /// Directory names including their length prefix and terminator.
+    pub dir_names: u64,
+    /// File names including their terminator and, for v001, their offset.
+    pub file_names: u64,
+    /// Names embedded in the data blocks of files including their length prefix.
+    pub embedded_names: u64,
+}
+impl NameTable {
+    pub fn total(&self) -> u64 {
+        self.dir_names + self.file_names + self.embedded_names
+    }
+}
+
+/// Breakdown of the content of an archive.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Stats {
+    pub totals: Group,
+    /// Files by their lowercase top level directory. Files at the root are under the empty name.
+    pub by_dir: BTreeMap<String, Group>,
+    /// Files by their lowercase extension without the dot. Files without extension are under the empty name.
+    pub by_extension: BTreeMap<String, Group>,
+    /// The largest files by uncompressed size, largest first.
+    pub largest: Vec<ChecksumEntry>,
+    /// Groups of files with the same data, most wasted bytes first.
+    pub duplicates: Vec<Duplicates>,
+    pub names: NameTable,
+}
+impl Stats {
+    /// Compute the statistics of an archive, keeping the `largest` largest files.
+    /// Every file is extracted to determine its uncompressed size and find duplicates.
+    /// Only the name tables included in the header are counted.
+    pub fn new<R: Reader<Root = SomeRoot, Header = SomeHeader>>(reader: &mut R, largest: usize) -> io::Result<Self> {
+        let header = reader.header();
+        let root = reader.list()?;
+        let mut stats = Stats::default();
+        let entries = match &root {
+            SomeRoot::V001(files) => Entries::files(files),
+            SomeRoot::V10X(dirs) => {
+                if header.includes_dir_names() {
+                    for dir in dirs {
+                        stats.names.dir_names += name_len(dir.id.name.as_deref()) + 2;
+                    }
+                }
+                Entries::dirs(dirs)
+            },
+        };
+        let includes_file_names = header.includes_file_names();
+        let offset_len = if matches!(root, SomeRoot::V001(_)) { 4 } else { 0 };
+
+        let mut by_data: HashMap<_, Vec<ChecksumEntry>> = HashMap::new();
+        for (dir, file) in entries {
+            let entry = ChecksumEntry::new(reader, dir, file)?;
+            if includes_file_names {
+                stats.names.file_names += name_len(file.id.name.as_deref()) + 1 + offset_len;
+            }
+            stats.names.embedded_names += file.embedded_name.as_deref()
+                .map_or(0, |name| name_len(Some(name)) + 1);
+            stats.totals.add(&entry);
+            stats.by_dir.entry(top_level_dir(dir, file)).or_default().add(&entry);
+            stats.by_extension.entry(extension(&entry.path)).or_default().add(&entry);
+            stats.largest.push(entry.clone());
+            by_data.entry((entry.sha256, entry.uncompressed_size)).or_default().push(entry);
+        }
+
+        stats.largest.sort_by(|a, b| b.uncompressed_size.cmp(&a.uncompressed_size).then_with(|| a.path.cmp(&b.path)));
+        stats.largest.truncate(largest);
+        stats.duplicates = by_data.into_values()
+            .filter(|files| files.len() > 1)
+            .map(|files| {
+                let stored: u64 = files.iter().map(|file| file.size as u64).sum();
+                let smallest = files.iter().map(|file| file.size as u64).min().unwrap_or_default();
+                Duplicates { files, wasted: stored - smallest }
+            })
+            .collect();
+        stats.duplicates.sort_by(|a, b| b.wasted.cmp(&a.wasted).then_with(|| a.files[0].path.cmp(&b.files[0].path)));
+        Ok(stats)
+    }
+}
+
+fn name_len(name: Option<&str>) -> u64 {
+    name.map_or(0, str::len) as u64
+}
+
+fn top_level_dir(dir: Option<&Dir>, file: &File) -> String {
+    let path = match dir {
+        Some(dir) => dir.id.to_string(),
+        None => file.id.to_string().rsplit_once('/').map(|(dir, _)| dir.to_owned()).unwrap_or_default(),
+    };
+    let path = path.trim_matches('/');
+    if path == "." {
+        return String::new();
+    }
+    path.split('/').next().unwrap_or_default().to_lowercase()
+}
+
+fn extension(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{ArchiveFlagV105, ForSomeVersion, ForSomeVersion10X, SomeReader, WriterV001, WriterV105};
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    fn dirs() -> Vec<Dir<Vec<u8>>> {
+        vec![
+            Dir::new("meshes\\armor", [File::new("a.nif", vec![1; 10]), File::new("b.nif", vec![2; 30])]),
+            Dir::new("textures", [File::new("a.dds", vec![1; 10]), File::new("readme", vec![3; 5])]),
+        ]
+    }
+
+    #[test]
+    fn stats_by_dir_and_extension() {
+        for bytes in [bsa_bytes(WriterV105::default(), dirs()), bsa_bytes(WriterV001::default(), dirs())] {
+            let mut bsa = SomeReader::read_bsa(bytes)
+                .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+            let stats = Stats::new(&mut bsa, 2)
+                .unwrap_or_else(|err| panic!("could not compute stats {}", err));
+
+            assert_eq!(stats.totals, Group { files: 4, stored: 55, uncompressed: 55 }, "totals");
+            assert_eq!(stats.by_dir.keys().collect::<Vec<_>>(), vec!["meshes", "textures"], "dirs");
+            assert_eq!(stats.by_dir["meshes"].uncompressed, 40, "meshes");
+            assert_eq!(stats.by_extension.keys().collect::<Vec<_>>(), vec!["", "dds", "nif"], "extensions");
+            assert_eq!(stats.by_extension["nif"].files, 2, "nif");
+            let largest: Vec<&str> = stats.largest.iter().map(|entry| entry.path.as_str()).collect();
+            assert_eq!(largest, vec!["meshes/armor/b.nif", "meshes/armor/a.nif"], "largest");
+            assert_eq!(stats.duplicates.len(), 1, "duplicates");
+            assert_eq!(stats.duplicates[0].wasted, 10, "wasted");
+            assert!(stats.names.file_names > 0, "file names");
+        }
+    }
+
+    #[test]
+    fn names_only_of_included_tables() {
+        let mut bsa = SomeReader::read_bsa(bsa_bytes(WriterV105::default(), dirs()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let header = match bsa.header() {
+            ForSomeVersion::V10X(ForSomeVersion10X::V105(header)) => header,
+            header => panic!("unexpected header {:?}", header),
+        };
+        let stats = Stats::new(&mut bsa, 0)
+            .unwrap_or_else(|err| panic!("could not compute stats {}", err));
+        assert_eq!(stats.names.dir_names, (header.total_dir_name_length + header.dir_count) as u64, "dir names");
+        assert_eq!(stats.names.file_names, header.total_file_name_length as u64, "file names");
+
+        let mut writer = WriterV105::default();
+        writer.archive_flags.remove(ArchiveFlagV105::IncludeDirectoryNames | ArchiveFlagV105::IncludeFileNames);
+        let mut bsa = SomeReader::read_bsa(bsa_bytes(writer, dirs()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let stats = Stats::new(&mut bsa, 0)
+            .unwrap_or_else(|err| panic!("could not compute stats {}", err));
+        assert_eq!(stats.names, NameTable::default(), "without names");
+    }
+}
@@ -1,19 +1,22 @@
 use std::{
     io::{self, Read, Write},
-    str::{self, FromStr},
+    str::FromStr,
     convert::TryFrom,
 };
 use thiserror::Error;
 
 use crate::bin::{Readable, VarSize, Writable, read_struct};
+use crate::codepage::Codepage;
 
 
 #[derive(Debug, Error)]
 pub enum StrError {
     #[error("string may only be {0} chars or less long")]
     TooLong(usize),
-    #[error("{0}")]
-    Utf8Error(#[from] str::Utf8Error),
+    #[error("\"{0}\" can not be represented in codepage {1}")]
+    Unmappable(String, Codepage),
+    #[error("string is not valid in codepage {0}")]
+    Malformed(Codepage),
 }
 impl From<StrError> for io::Error {
     fn from(err: StrError) -> Self {
@@ -21,26 +24,34 @@ impl From<StrError> for io::Error {
     }
 }
 
-/// A [`String`] That is represented by a length byte followed by its chars.
+/// A string That is represented by a length byte followed by its chars.
+/// The chars are stored encoded in the codepage of the archive.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct BString(String);
+pub struct BString(Vec<u8>);
+impl BString {
+    pub fn new<B: AsRef<[u8]>> (chars: B) -> Result<Self, StrError> {
+        Self::try_from(chars.as_ref().to_vec())
+    }
+
+    pub fn encode(s: &str, codepage: Codepage) -> Result<Self, StrError> {
+        Self::new(codepage.encode(s)?)
+    }
 
+    pub fn decode(&self, codepage: Codepage) -> Result<String, StrError> {
+        codepage.decode(&self.0).map(String::from)
+    }
+}
 impl TryFrom<Vec<u8>> for BString {
     type Error = StrError;
     fn try_from(chars: Vec<u8>) -> Result<Self, StrError> {
-        from_utf8(chars)
+        check_len(&chars, 255)?;
+        Ok(Self(chars))
     }
 }
 impl FromStr for BString {
     type Err = StrError;
     fn from_str(s: &str) -> Result<Self, StrError> {
-        check_len(s, 255)?;
-        Ok(Self(s.to_owned()))
-    }
-}
-impl ToString for BString {
-    fn to_string(&self) -> String {
-        self.0.clone()
+        Self::new(s)
     }
 }
 impl Readable for BString {
@@ -48,47 +59,52 @@ impl Readable for BString {
         let length: u8 = read_struct(&mut reader)?;
         let mut chars: Vec<u8> = vec![0u8; length as usize];
         reader.read_exact(&mut chars)?;
-        let s = Self::try_from(chars)?;
-        Ok(s)
+        Ok(Self(chars))
     }
 }
 impl VarSize for BString {
     fn size(&self) -> usize {
-        self.0.len() + 2 // length byte + chars + null
+        self.0.len() + 1 // length byte + chars
     }
 }
 impl Writable for BString {
     fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
         (self.0.len() as u8).write(&mut out)?;
-        self.0.as_bytes().write(&mut out)?;
-        (0 as u8).write(&mut out)
+        self.0.write(&mut out)
     }
 }
 
-/// A [`String`] That is represented by its chars followed by a 0 byte.
+/// A string That is represented by its chars followed by a 0 byte.
+/// The chars are stored encoded in the codepage of the archive.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct ZString(String);
+pub struct ZString(Vec<u8>);
 impl ZString {
     pub fn new<B: AsRef<[u8]>> (chars: B) -> Result<Self, StrError> {
-        from_utf8(chars)
+        Self::try_from(chars.as_ref().to_vec())
+    }
+
+    pub fn encode(s: &str, codepage: Codepage) -> Result<Self, StrError> {
+        Self::new(codepage.encode(s)?)
+    }
+
+    pub fn decode(&self, codepage: Codepage) -> Result<String, StrError> {
+        codepage.decode(&self.0).map(String::from)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
     }
 }
 impl FromStr for ZString {
     type Err = StrError;
     fn from_str(s: &str) -> Result<Self, StrError> {
-        Ok(Self(s.to_owned()))
+        Self::new(s)
     }
 }
 impl TryFrom<Vec<u8>> for ZString {
     type Error = StrError;
     fn try_from(chars: Vec<u8>) -> Result<Self, StrError> {
-        from_utf8(chars)
-    }
-}
-
-impl ToString for ZString {
-    fn to_string(&self) -> String {
-        self.0.clone()
+        Ok(Self(chars))
     }
 }
 impl Readable for ZString {
@@ -101,8 +117,7 @@ impl Readable for ZString {
             }
             chars.push(c);
         }
-        let s = Self::try_from(chars)?;
-        Ok(s)
+        Ok(Self(chars))
     }
 }
 impl VarSize for ZString {
@@ -112,38 +127,41 @@ impl VarSize for ZString {
 }
 impl Writable for ZString {
     fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
-        self.0.as_bytes().write(&mut writer)?;
+        self.0.write(&mut writer)?;
         (0 as u8).write(writer)
     }
 }
 
-/// A [`String`] That is represented by a length byte followed its chars followed by a 0 byte.
+/// A string That is represented by a length byte followed its chars followed by a 0 byte.
 /// The length byte includes the 0 byte.
+/// The chars are stored encoded in the codepage of the archive.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct BZString(String);
+pub struct BZString(Vec<u8>);
 
 impl BZString {
     pub fn new<B: AsRef<[u8]>> (chars: B) -> Result<Self, StrError> {
-        from_utf8(chars)
+        Self::try_from(chars.as_ref().to_vec())
+    }
+
+    pub fn encode(s: &str, codepage: Codepage) -> Result<Self, StrError> {
+        Self::new(codepage.encode(s)?)
+    }
+
+    pub fn decode(&self, codepage: Codepage) -> Result<String, StrError> {
+        codepage.decode(&self.0).map(String::from)
     }
 }
 impl TryFrom<Vec<u8>> for BZString {
     type Error = StrError;
     fn try_from(chars: Vec<u8>) -> Result<Self, StrError> {
-        from_utf8(chars)
+        check_len(&chars, 254)?;
+        Ok(Self(chars))
     }
 }
 impl FromStr for BZString {
     type Err = StrError;
     fn from_str(s: &str) -> Result<Self, StrError> {
-        check_len(s, 254)?;
-        Ok(Self(s.to_owned()))
-    }
-}
-
-impl ToString for BZString {
-    fn to_string(&self) -> String {
-        self.0.clone()
+        Self::new(s)
     }
 }
 impl VarSize for BZString {
@@ -157,25 +175,19 @@ impl Readable for BZString {
         let mut chars: Vec<u8> = vec![0u8; (len - 1) as usize]; // length field includes null.
         reader.read_exact(&mut chars)?;
         u8::read_bin(&mut reader)?; // skip null byte
-        let s = Self::try_from(chars)?;
-        Ok(s)
+        Ok(Self(chars))
     }
 }
 impl Writable for BZString {
     fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
         (self.0.len() as u8 + 1).write(&mut out)?;
-        self.0.as_bytes().write(&mut out)?;
+        self.0.write(&mut out)?;
         (0 as u8).write(&mut out)
     }
 }
 
-fn from_utf8<B: AsRef<[u8]>, S: FromStr<Err = StrError>>(chars: B) -> Result<S, StrError> {
-    let s = str::from_utf8(chars.as_ref())?;
-    S::from_str(s)
-}
-
-fn check_len(s: &str, max_len: usize) -> Result<(), StrError> {
-    if s.len() > max_len {
+fn check_len(chars: &[u8], max_len: usize) -> Result<(), StrError> {
+    if chars.len() > max_len {
         Err(StrError::TooLong(max_len))
     } else {
         Ok(())
@@ -192,32 +204,43 @@ mod tests {
  
     #[test]
     fn write_read_identity_bstring_zero_len() {
-        write_read_identity(BString("".to_owned()));
+        write_read_identity(BString("".into()));
     }
 
     #[test]
     fn write_read_identity_zstring_zero_len() {
-        write_read_identity(ZString("".to_owned()));
+        write_read_identity(ZString("".into()));
     }
 
     #[test]
     fn write_read_identity_bzstring_zero_len() {
-        write_read_identity(BZString("".to_owned()));
+        write_read_identity(BZString("".into()));
     }
 
     #[test]
     fn write_read_identity_bstring_some_chars() {
-        write_read_identity(BString("asdf_basdf".to_owned()));
+        write_read_identity(BString("asdf_basdf".into()));
     }
 
     #[test]
     fn write_read_identity_zstring_some_chars() {
-        write_read_identity(ZString("asdf_basdf".to_owned()));
+        write_read_identity(ZString("asdf_basdf".into()));
     }
 
     #[test]
     fn write_read_identity_bzstring_some_chars() {
-        write_read_identity(BZString("asdf_basdf".to_owned()));
+        write_read_identity(BZString("asdf_basdf".into()));
+    }
+
+    #[test]
+    fn encode_decode_identity_bzstring_codepage() {
+        let s = BZString::encode("доспехи", Codepage::WINDOWS_1251)
+            .unwrap_or_else(|err| panic!("could not encode {}", err));
+        let actual = write_read(&s).decode(Codepage::WINDOWS_1251)
+            .unwrap_or_else(|err| panic!("could not decode {}", err));
+
+        assert_eq!(s.size(), 9, "size");
+        assert_eq!(actual, "доспехи");
     }
 
     #[test]
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::iter;
+
+use crate::{EntryId, SomeRoot};
+use crate::read;
+
+
+/// A file of an [`ArchiveTree`] together with its complete path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeFile {
+    /// The complete path of the file using `/` as separator.
+    pub path: String,
+    pub file: read::File,
+}
+impl TreeFile {
+    /// The last component of the path.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Number of files and their size inside a directory and all its subdirectories.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub files: usize,
+    pub size: u64,
+}
+
+/// A directory of an [`ArchiveTree`].
+/// Unlike [`read::Dir`] it contains its subdirectories.
+/// Directories that are only implied by the paths of other directories have no [`id`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeDir {
+    /// The complete path of the directory using `/` as separator. Empty for the root.
+    pub path: String,
+    /// The id of the directory inside the archive if it exists there.
+    pub id: Option<EntryId>,
+    pub dirs: BTreeMap<String, TreeDir>,
+    pub files: Vec<TreeFile>,
+}
+impl TreeDir {
+    /// The last component of the path.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Find a subdirectory by its path relative to this directory.
+    pub fn get<S: AsRef<str>>(&self, path: S) -> Option<&TreeDir> {
+        components(path.as_ref())
+            .try_fold(self, |dir, name| dir.dirs.get(&name))
+    }
+
+    /// This directory and all its subdirectories, parents before their children.
+    pub fn walk(&self) -> Box<dyn Iterator<Item = &TreeDir> + '_> {
+        Box::new(iter::once(self)
+            .chain(self.dirs.values().flat_map(TreeDir::walk)))
+    }
+
+    /// All files of this directory and its subdirectories.
+    pub fn all_files(&self) -> impl Iterator<Item = &TreeFile> {
+        self.walk().flat_map(|dir| dir.files.iter())
+    }
+
+    pub fn totals(&self) -> Totals {
+        self.all_files()
+            .fold(Totals::default(), |totals, file| Totals {
+                files: totals.files + 1,
+                size: totals.size + file.file.size as u64,
+            })
+    }
+
+    fn dir_mut(&mut self, path: &str) -> &mut TreeDir {
+        components(path)
+            .fold(self, |dir, name| {
+                let path = if dir.path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}/{}", dir.path, name)
+                };
+                dir.dirs.entry(name)
+                    .or_insert_with(|| TreeDir { path, ..TreeDir::default() })
+            })
+    }
+
+    fn push_file(&mut self, name: String, file: read::File) {
+        let path = if self.path.is_empty() {
+            name
+        } else {
+            format!("{}/{}", self.path, name)
+        };
+        self.files.push(TreeFile { path, file });
+    }
+}
+
+/// Nested view of the flat directory list of an archive.
+/// Entries without a name use their hash as name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveTree {
+    pub root: TreeDir,
+}
+impl ArchiveTree {
+    pub fn new(root: &SomeRoot) -> Self {
+        let mut tree = ArchiveTree::default();
+        match root {
+            SomeRoot::V001(files) => {
+                for file in files {
+                    let path = file.id.to_string();
+                    let (dir, name) = path.rsplit_once('/')
+                        .unwrap_or(("", &path));
+                    tree.root.dir_mut(dir)
+                        .push_file(name.to_owned(), file.clone());
+                }
+            },
+            SomeRoot::V10X(dirs) => {
+                for dir in dirs {
+                    tree.push_dir(dir);
+                }
+            },
+        }
+        tree
+    }
+
+    fn push_dir(&mut self, dir: &read::Dir) {
+        let tree_dir = self.root.dir_mut(&dir.id.to_string());
+        tree_dir.id = Some(dir.id.clone());
+        for file in dir {
+            tree_dir.push_file(file.id.to_string(), file.clone());
+        }
+    }
+
+    /// Find a directory by its path.
+    pub fn get<S: AsRef<str>>(&self, path: S) -> Option<&TreeDir> {
+        self.root.get(path)
+    }
+
+    /// All directories, parents before their children.
+    pub fn walk(&self) -> impl Iterator<Item = &TreeDir> {
+        self.root.walk()
+    }
+
+    /// All files of the archive.
+    pub fn files(&self) -> impl Iterator<Item = &TreeFile> {
+        self.root.all_files()
+    }
+}
+impl From<&SomeRoot> for ArchiveTree {
+    fn from(root: &SomeRoot) -> Self {
+        ArchiveTree::new(root)
+    }
+}
+
+fn components(path: &str) -> impl Iterator<Item = String> + '_ {
+    path.split(|c| c == '/' || c == '\\')
+        .filter(|name| !name.is_empty() && *name != ".")
+        .map(str::to_lowercase)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{SomeRoot, WriterV105};
+    use crate::read::Reader;
+    use crate::v105::ReaderV105;
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    fn some_tree() -> ArchiveTree {
+        let dirs = vec![
+            Dir::new("a", [File::new("x", vec![1, 2])]),
+            Dir::new("a\\b\\c", [File::new("y", vec![3]), File::new("z", vec![4, 5, 6])]),
+        ];
+        let mut bsa = ReaderV105::read_bsa(bsa_bytes(WriterV105::default(), dirs))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let root = SomeRoot::V10X(bsa.list()
+            .unwrap_or_else(|err| panic!("could not read dirs {}", err)));
+        ArchiveTree::new(&root)
+    }
+
+    #[test]
+    fn nests_dirs() {
+        let tree = some_tree();
+
+        let a = tree.get("a").unwrap_or_else(|| panic!("missing dir a"));
+        assert!(a.id.is_some(), "a.id");
+        let b = a.get("b").unwrap_or_else(|| panic!("missing dir a/b"));
+        assert_eq!(b.id, None, "b.id");
+        assert_eq!(b.path, "a/b", "b.path");
+        let c = tree.get("A/B/C").unwrap_or_else(|| panic!("missing dir a/b/c"));
+        assert_eq!(c.files[1].path, "a/b/c/z", "z.path");
+        assert_eq!(c.files[1].name(), "z", "z.name()");
+    }
+
+    #[test]
+    fn walk_and_totals() {
+        let tree = some_tree();
+
+        let paths: Vec<&str> = tree.walk().map(|dir| dir.path.as_str()).collect();
+        assert_eq!(paths, vec!["", "a", "a/b", "a/b/c"], "walk");
+        assert_eq!(tree.root.totals().files, 3, "root files");
+        assert_eq!(tree.get("a/b").map(TreeDir::totals).map(|t| t.files), Some(2), "a/b files");
+    }
+}
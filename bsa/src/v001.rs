@@ -5,10 +5,11 @@ use std::io::{self, Read, Write, Seek, SeekFrom, copy};
 use bytemuck::{Pod, Zeroable};
 use thiserror::Error;
 
-use crate::{EntryId, Hash, Version};
-use crate::bin::{DataSource, Fixed, Positioned, Readable, ReadableFixed, Writable, WritableFixed, derive_readable_via_pod, derive_writable_via_pod};
+use crate::{Codepage, EntryId, Hash, Version};
+use crate::bin::{DataSource, Fixed, Positioned, Readable, ReadableFixed, VarSize, Writable, WritableFixed, derive_readable_via_pod, derive_writable_via_pod};
 use crate::str::{StrError, ZString};
 use crate::read::{self, Reader};
+use crate::progress::{Observer, ObservedRead, check_cancelled};
 use crate::write::{self, Writer};
 use crate::version::MagicNumber;
 
@@ -25,8 +26,10 @@ pub enum V001WriteError {
     StrErr(String, StrError),
 }
 
+/// Like all headers only serialized, since an untagged `SomeHeader` could not be deserialized unambiguously.
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct HeaderV001 {
     pub offset_hash_table: u32,
     pub file_count: u32,
@@ -65,19 +68,34 @@ const fn offset_after_index(header: &HeaderV001) -> u64 {
     offset_after_header() + header.offset_hash_table as u64 + (size_of::<Hash>() * header.file_count as usize) as u64
 }
 
-pub struct V001 {}
+pub struct V001 {
+    /// The codepage names are encoded with.
+    pub codepage: Codepage,
+}
 pub type WriterV001 = V001;
 impl Default for V001 {
     fn default() -> Self {
-        Self {}
+        Self {
+            codepage: Codepage::default(),
+        }
     }
 }
 pub struct ReaderV001<R> {
     reader: R,
     header: HeaderV001,
     files: Option<Vec<read::File>>,
+    codepage: Codepage,
 }
 impl<R: Read + Seek> ReaderV001<R> {
+    /// The index of the archive. It is read on first access.
+    fn index(&mut self) -> io::Result<&[read::File]> {
+        if self.files.is_none() {
+            let files = self.files()?;
+            self.files = Some(files);
+        }
+        Ok(self.files.get_or_insert_with(Vec::new))
+    }
+
     fn files(&mut self) -> io::Result<Vec<read::File>> {
         let file_count = self.header.file_count as usize;
         self.reader.seek(SeekFrom::Start(offset_after_header()))?;
@@ -92,19 +110,17 @@ impl<R: Read + Seek> ReaderV001<R> {
             .map(|((rec, name_offset), hash)| {
                 let name_pos = offset_names_start(file_count as u64) + name_offset as u64;
                 self.reader.seek(SeekFrom::Start(name_pos))?;
-                let name = match ZString::read_bin(&mut self.reader) {
-                    Ok(n) => n,
-                    Err(err) => panic!("could not read name at {}: {}", name_pos, err),
-                };
+                let name = ZString::read_bin(&mut self.reader)?;
 
                 Ok(read::File {
                     id: EntryId {
                         hash,
-                        name: Some(name.to_string()),
+                        name: Some(name.decode(self.codepage)?),
                     },
                     compressed: false,
                     size: rec.size as usize,
                     offset: offset_after_index(&self.header) + rec.offset as u64,
+                    embedded_name: None,
                 })
             })
             .collect()
@@ -122,51 +138,62 @@ where R: Read + Seek {
             reader,
             header,
             files: None,
+            codepage: Codepage::default(),
         })
     }
 
     fn header(&self) -> HeaderV001 { self.header }
-    fn list(&mut self) -> io::Result<Vec<read::File>> {
-        if let Some(files) = &self.files {
-            Ok(files.to_vec())
-        } else {
-            let files = self.files()?;
-            self.files = Some(files.to_vec());
-            Ok(files)
+    fn codepage(&self) -> Codepage { self.codepage }
+    fn index_size(&self) -> u64 { offset_after_index(&self.header) }
+    fn set_codepage(&mut self, codepage: Codepage) {
+        if self.codepage != codepage {
+            self.codepage = codepage;
+            self.files = None;
         }
     }
+    fn entries(&mut self) -> io::Result<read::Entries<'_>> {
+        self.index().map(read::Entries::files)
+    }
+    fn list(&mut self) -> io::Result<Vec<read::File>> {
+        self.index().map(|files| files.to_vec())
+    }
     fn extract<W: Write>(&mut self, file: &read::File, mut out: W) -> io::Result<()> {
         self.reader.seek(SeekFrom::Start(file.offset))?;
         let mut data = (&mut self.reader).take(file.size as u64);
         copy(&mut data, &mut out)?;
         Ok(())
     }
+    fn uncompressed_size(&mut self, file: &read::File) -> io::Result<u64> { Ok(file.size as u64) }
 }
 impl Writer for V001 {
     type Err = V001WriteError;
     
-    fn write_bsa<DS, D, W>(&self, dirs: DS, mut out: W) -> Result<(), V001WriteError>
+    fn write_bsa_observed<DS, D, W>(&self, dirs: DS, mut out: W, observer: &mut dyn Observer) -> Result<(), V001WriteError>
     where
         DS: IntoIterator<Item = write::Dir<D>>,
         D: DataSource,
         W: Write + Seek,
     {
+        let dirs: Vec<write::Dir<D>> = dirs.into_iter().collect();
+        observer.started(write::totals(&dirs));
         let mut offset_hash_table: u32 = 0;
-        let mut files: BTreeMap<Hash, (String, D)> = BTreeMap::new(); // has to be ordered by hash
+        let mut files: BTreeMap<Hash, (String, ZString, D)> = BTreeMap::new(); // has to be ordered by hash
         for dir in dirs {
             for file in dir.files {
                 if file.compressed == Some(true) {
                     return Err(V001WriteError::CompressionNotSupported)
                 }
                 let name = format!("{}\\{}",
-                    dir.name.to_lowercase(),
-                    file.name.to_lowercase());
-                offset_hash_table += (size_of::<(FileRecord, u32)>() + name.len() + 1) as u32;
-                let hash = Hash::v001(&name);
-                if let Some((other, _)) = files.get(&hash) {
+                    dir.name.to_ascii_lowercase(),
+                    file.name.to_ascii_lowercase());
+                let encoded = ZString::encode(&name, self.codepage)
+                    .map_err(|err| V001WriteError::StrErr(name.clone(), err))?;
+                offset_hash_table += (size_of::<(FileRecord, u32)>() + encoded.size()) as u32;
+                let hash = Hash::v001_encoded(encoded.as_bytes());
+                if let Some((other, _, _)) = files.get(&hash) {
                     return Err(V001WriteError::HashCollision(name, other.clone()))
                 }
-                files.insert(hash, (name, file.data));
+                files.insert(hash, (name, encoded, file.data));
             }
         }
 
@@ -190,24 +217,26 @@ impl Writer for V001 {
             name_offsets.push(Positioned::new(0, &mut out)?);
         }
         let offset_names_start = offset_names_start(files.len() as u64) as u32;
-        for (name_offset, (_, (name, _))) in name_offsets.iter_mut().zip(&files) {
+        for (name_offset, (_, (_, name, _))) in name_offsets.iter_mut().zip(&files) {
             name_offset.data = out.stream_position()? as u32 - offset_names_start;
-            println!("write name at {}", name_offset.data);
-            let name = ZString::new(&name)
-                .map_err(|err| V001WriteError::StrErr(name.clone(), err))?;
             name.write(&mut out)?;
             name_offset.update(&mut out)?;
         }
         for (hash, _) in &files {
             hash.write(&mut out)?;
         }
-        for (rec, (_, (_, data))) in recs.iter_mut().zip(&files) {
+        for (rec, (_, (name, _, data))) in recs.iter_mut().zip(&files) {
+            check_cancelled(observer)?;
+            observer.file_started(name, data.size());
             let pos = out.stream_position()? as u32;
-            println!("write file data at: {}", pos);
             rec.data.offset = pos - offset_after_index(&header) as u32;
-            let mut data = data.open()?;
+            let mut data = ObservedRead {
+                inner: data.open()?,
+                observer: &mut *observer,
+            };
             rec.data.size = copy(&mut data, &mut out)? as u32;
             rec.update(&mut out)?;
+            observer.file_finished(name);
         }
         Ok(())
     }
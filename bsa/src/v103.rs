@@ -48,6 +48,7 @@ impl ToArchiveBitFlags for ArchiveFlagV103 {
     fn is_compressed_by_default() -> Self { ArchiveFlagV103::CompressedArchive }
     fn includes_file_names() -> Self { ArchiveFlagV103::IncludeFileNames }
     fn includes_dir_names() -> Self { ArchiveFlagV103::IncludeDirectoryNames }
+    fn xbox_archive() -> Self { ArchiveFlagV103::Xbox360Archive }
 }
 
 pub enum V103 {}
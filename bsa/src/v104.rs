@@ -57,6 +57,7 @@ impl ToArchiveBitFlags for ArchiveFlagV104 {
     fn is_compressed_by_default() -> Self { ArchiveFlagV104::CompressedArchive }
     fn includes_file_names() -> Self { ArchiveFlagV104::IncludeFileNames }
     fn includes_dir_names() -> Self { ArchiveFlagV104::IncludeDirectoryNames }
+    fn xbox_archive() -> Self { ArchiveFlagV104::Xbox360Archive }
     fn embed_file_names() -> Option<Self> { Some(ArchiveFlagV104::EmbedFileNames) }
 }
 
@@ -1,7 +1,7 @@
 use bytemuck::{Zeroable, Pod};
 
 use crate::version::Version10X;
-use crate::v10x::{self, ReaderV10X, WriterV10X, Versioned};
+use crate::v10x::{self, ReaderV10X, WriterV10X, SwapBytes, Versioned};
 use crate::hash::Hash;
 use crate::compress::Lz4;
 use crate::v104::{HeaderV104, ArchiveFlagV104};
@@ -19,6 +19,18 @@ pub struct RawDirRecord {
 derive_var_size_via_size_of!(RawDirRecord);
 derive_readable_via_pod!(RawDirRecord);
 derive_writable_via_pod!(RawDirRecord);
+/// The offset is a `u64` of which only the low half is used.
+impl SwapBytes for RawDirRecord {
+    fn swap_bytes(self) -> Self {
+        Self {
+            name_hash: self.name_hash.swap_bytes(),
+            file_count: self.file_count.swap_bytes(),
+            _padding_pre: self._padding_pre.swap_bytes(),
+            offset: self._padding_post.swap_bytes(),
+            _padding_post: self.offset.swap_bytes(),
+        }
+    }
+}
 impl From<RawDirRecord> for v10x::DirRecord {
     fn from(rec: RawDirRecord) -> Self {
         Self {
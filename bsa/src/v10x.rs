@@ -2,17 +2,19 @@ use std::io::{Read, Seek, SeekFrom, Result, Write, copy};
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::mem::size_of;
-use std::str::{self, FromStr};
 use std::fmt;
 use bytemuck::{Pod, Zeroable};
 use enumflags2::{bitflags, BitFlags, BitFlag};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
 
 use crate::bin::{self, DataSource, Fixed, Positioned, Readable, ReadableFixed, ReadableParam, VarSize, Writable, WritableFixed, derive_readable_via_pod, derive_writable_via_pod, read_struct};
 use crate::compress::Compression;
 use crate::str::{BZString, BString, ZString};
-use crate::{EntryId, Hash};
+use crate::{Codepage, EntryId, Hash};
 use crate::version::{Version, Version10X, MagicNumber};
 use crate::read::{self, Reader};
+use crate::progress::{Observer, ObservedRead, check_cancelled};
 use crate::write::{self, Writer};
 
 
@@ -26,11 +28,28 @@ pub trait ToArchiveBitFlags: BitFlag + fmt::Debug {
     
     fn includes_dir_names() -> Self;
 
+    fn xbox_archive() -> Self;
+
     fn embed_file_names() -> Option<Self> {
         None
     }
 }
 
+/// Converts numbers between little-endian and big-endian, which Xbox 360 archives use after the header.
+pub trait SwapBytes {
+    fn swap_bytes(self) -> Self;
+}
+impl SwapBytes for u32 {
+    fn swap_bytes(self) -> Self {
+        u32::swap_bytes(self)
+    }
+}
+impl SwapBytes for Hash {
+    fn swap_bytes(self) -> Self {
+        Hash::swap_bytes(self)
+    }
+}
+
 #[bitflags]
 #[repr(u16)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -65,17 +84,29 @@ derive_var_size_via_size_of!(RawHeader);
 derive_readable_fixed_via_default!(RawHeader);
 derive_writable_fixed_via_default!(RawHeader);
 
+/// Flags are serialized as the list of their names.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(bound(serialize = "AF: fmt::Debug")))]
 pub struct HeaderV10X<AF: BitFlag> {
     pub offset: u32,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_flag_names"))]
     pub archive_flags: BitFlags<AF>,
     pub dir_count: u32,
     pub file_count: u32,
     pub total_dir_name_length: u32,
     pub total_file_name_length: u32,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_flag_names"))]
     pub file_flags: BitFlags<FileFlag>,
     pub padding: u16,
 }
+#[cfg(feature = "serde")]
+fn serialize_flag_names<F, S>(flags: &BitFlags<F>, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    F: BitFlag + fmt::Debug,
+    S: serde::Serializer,
+{
+    serializer.collect_seq(flags.iter().map(|flag| format!("{:?}", flag)))
+}
 impl<AF: ToArchiveBitFlags + std::cmp::PartialEq> Eq for HeaderV10X<AF> {}
 impl<AF: BitFlag> HeaderV10X<AF> {
     fn effective_total_dir_name_len(&self) -> usize {
@@ -178,6 +209,9 @@ pub struct ReaderV10X<R, T, C, AF: ToArchiveBitFlags, RDR> {
     pub(crate) reader: R,
     pub(crate) header: HeaderV10X<AF>,
     pub(crate) dirs: Option<Vec<read::Dir>>,
+    /// The index while the embedded names are read, kept if reading them fails so it can be resumed.
+    partial_dirs: Option<Vec<read::Dir>>,
+    pub(crate) codepage: Codepage,
     phantom_t: PhantomData<T>,
     phantom_c: PhantomData<C>,
     phantom_rdr: PhantomData<RDR>,
@@ -200,6 +234,15 @@ where
         self.offset_after_header() + dir_records_size + dir_names_size + file_records_size
     }
 
+    /// Records of Xbox 360 archives are big-endian.
+    fn native<A: SwapBytes>(&self, record: A) -> A {
+        if self.header.has(AF::xbox_archive()) {
+            record.swap_bytes()
+        } else {
+            record
+        }
+    }
+
     fn offset_after_header(&self) -> usize {
         size_of::<(MagicNumber, Version10X, RawHeader)>()
     }
@@ -209,7 +252,7 @@ where
         Ok(if self.header.has(AF::includes_file_names()) {
             let names = ZString::read_bin_many(&mut self.reader, self.header.file_count as usize)?;
             names.iter()
-                .map(|name| (Hash::v10x(name.to_string().as_str()), name.clone()))
+                .map(|name| (Hash::v10x_encoded(name.as_bytes()), name.clone()))
                 .collect()
         } else {
             HashMap::new()
@@ -217,7 +260,7 @@ where
     }
 
     fn read_dir(&mut self, file_names: &HashMap<Hash, ZString>, dir: &DirRecord) -> Result<read::Dir> {
-        let has_dir_name = self.header.has(AF::includes_file_names());
+        let has_dir_name = self.header.has(AF::includes_dir_names());
         
         self.reader.seek(SeekFrom::Start(
             dir.offset as u64 - self.header.total_file_name_length as u64))?;
@@ -227,33 +270,126 @@ where
             id: EntryId {
                 hash: dir.name_hash,
                 name: dir_content.name
-                    .map(|n| n.to_string()),
+                    .map(|n| n.decode(self.codepage))
+                    .transpose()?,
             },
-            files: dir_content.files.iter()
-                .map(|file| self.to_file(&file_names, file))
-                .collect(),
+            files: dir_content.files.into_iter()
+                .map(|file| self.to_file(&file_names, &self.native(file)))
+                .collect::<Result<Vec<read::File>>>()?,
         })
     }
 
-    fn to_file(&mut self, file_names: &HashMap<Hash, ZString>, file: &FileRecord) -> read::File {
+    fn to_file(&mut self, file_names: &HashMap<Hash, ZString>, file: &FileRecord) -> Result<read::File> {
         let compressed = if self.header.has(AF::is_compressed_by_default()) {
             !file.is_compression_bit_set()
         } else {
             file.is_compression_bit_set()
         };
 
-        read::File {
+        Ok(read::File {
             id: EntryId {
                 hash: file.name_hash,
                 name: file_names.get(&file.name_hash)
-                    .map(|n| n.to_string()),
+                    .map(|n| n.decode(self.codepage))
+                    .transpose()?,
             },
             compressed,
             offset: file.offset as u64,
             size: file.real_size() as usize,
+            embedded_name: None,
+        })
+    }
+
+    fn read_embedded_name(&mut self, file: &read::File) -> Result<String> {
+        self.reader.seek(SeekFrom::Start(file.offset))?;
+        let name = BString::read_bin(&mut self.reader)?;
+        Ok(name.decode(self.codepage)?)
+    }
+
+    /// Read the names embedded in the data blocks of all files.
+    /// Names of directories and files that are missing from the name tables
+    /// are filled in from the embedded names if their hashes match.
+    /// Files with an embedded name are skipped, so reading can be resumed after an error.
+    fn read_embedded_names(&mut self, dirs: &mut [read::Dir]) -> Result<()> {
+        for dir in dirs {
+            for file in &mut dir.files {
+                if file.embedded_name.is_some() {
+                    continue;
+                }
+                let path = self.read_embedded_name(file)?;
+                let path = path.replace('/', "\\");
+                let (dir_name, file_name) = match path.rsplit_once('\\') {
+                    Some((dir_name, file_name)) => (dir_name, file_name),
+                    None => ("", path.as_str()),
+                };
+                if dir.id.name.is_none() && self.codepage.hash_v10x(dir_name)? == dir.id.hash {
+                    dir.id.name = Some(dir_name.to_owned());
+                }
+                if file.id.name.is_none() && self.codepage.hash_v10x(file_name)? == file.id.hash {
+                    file.id.name = Some(file_name.to_owned());
+                }
+                file.embedded_name = Some(path);
+            }
         }
+        Ok(())
     }
 }
+impl<R, T, C, AF, RDR> ReaderV10X<R, T, C, AF, RDR>
+where
+    R: Read + Seek,
+    T: Versioned,
+    C: Compression,
+    AF: ToArchiveBitFlags,
+    RDR: Readable + SwapBytes + Sized + Copy,
+    DirRecord: From<RDR>,
+{
+    fn read_index(&mut self) -> Result<Vec<read::Dir>> {
+        self.reader.seek(SeekFrom::Start(self.offset_after_header() as u64))?;
+        let raw_dirs: Vec<DirRecord> = RDR::read_bin_many(&mut self.reader, self.header.dir_count as usize)?
+            .into_iter()
+            .map(|dir| DirRecord::from(self.native(dir)))
+            .collect();
+        let file_names = self.read_file_names()?;
+        raw_dirs.iter()
+            .map(|dir| self.read_dir(&file_names, dir))
+            .collect()
+    }
+
+    /// The index of the archive. It is read on first access.
+    fn dirs(&mut self) -> Result<&[read::Dir]> {
+        if self.dirs.is_none() {
+            let mut dirs = match self.partial_dirs.take() {
+                Some(dirs) => dirs,
+                None => self.read_index()?,
+            };
+            if self.header.has_any(&AF::embed_file_names()) {
+                if let Err(err) = self.read_embedded_names(&mut dirs) {
+                    self.partial_dirs = Some(dirs);
+                    return Err(err);
+                }
+            }
+            self.dirs = Some(dirs);
+        }
+        Ok(self.dirs.get_or_insert_with(Vec::new))
+    }
+
+    /// Seek past the embedded name of a file.
+    /// Returns the size of the remaining data block, including the uncompressed size field.
+    fn seek_data(&mut self, file: &read::File) -> Result<u64> {
+        self.reader.seek(SeekFrom::Start(file.offset))?;
+        let mut size = file.size as u64;
+        if self.header.has_any(&AF::embed_file_names()) {
+            let name_len: u8 = read_struct(&mut self.reader)?;
+            self.reader.seek(SeekFrom::Current(name_len as i64))?;
+            size = size.saturating_sub(name_len as u64 + 1);
+        }
+        Ok(size)
+    }
+}
+fn hash_collision(a: &str, b: &str, hash: Hash) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, HashCollision(a.to_owned(), b.to_owned(), hash))
+}
+
 pub trait Versioned {
     fn version() -> Version10X;
 }
@@ -263,7 +399,7 @@ where
     T: Versioned,
     C: Compression,
     AF: ToArchiveBitFlags + fmt::Debug,
-    RDR: Readable + Sized + Copy + fmt::Debug,
+    RDR: Readable + SwapBytes + Sized + Copy + fmt::Debug,
     DirRecord: From<RDR>,
 {
     type Header = HeaderV10X<AF>;
@@ -275,6 +411,8 @@ where
             reader,
             header,
             dirs: None,
+            partial_dirs: None,
+            codepage: Codepage::default(),
             phantom_t: PhantomData,
             phantom_c: PhantomData,
             phantom_rdr: PhantomData,
@@ -286,43 +424,63 @@ where
         self.header
     }
 
-    fn list(&mut self) -> Result<Vec<read::Dir>> {
-        if let Some(dirs) = &self.dirs {
-            Ok(dirs.to_vec())
+    fn codepage(&self) -> Codepage {
+        self.codepage
+    }
+
+    fn index_size(&self) -> u64 {
+        let file_names_size = if self.header.has(AF::includes_file_names()) {
+            self.header.total_file_name_length as u64
         } else {
-            self.reader.seek(SeekFrom::Start(self.offset_after_header() as u64))?;
-            let raw_dirs = RDR::read_bin_many(&mut self.reader, self.header.dir_count as usize)?;
-            let file_names = self.read_file_names()?;
-            let dirs = raw_dirs.iter()
-                .map(|dir| DirRecord::from(*dir) )
-                .map(|dir| self.read_dir(&file_names, &dir))
-                .collect::<Result<Vec<read::Dir>>>()?;
-            self.dirs = Some(dirs.to_vec());
-            Ok(dirs)
-        } 
+            0
+        };
+        self.offset_file_names() as u64 + file_names_size
     }
 
-    fn extract<W: Write>(&mut self, file: &read::File, mut writer: W) -> Result<()> {
-        self.reader.seek(SeekFrom::Start(file.offset))?;
-        
-        // skip name field
-        if self.header.has_any(&AF::embed_file_names()) {
-            let name_len: u8 = read_struct(&mut self.reader)?;
-            self.reader.seek(SeekFrom::Current(name_len as i64))?;
+    fn set_codepage(&mut self, codepage: Codepage) {
+        if self.codepage != codepage {
+            self.codepage = codepage;
+            self.dirs = None;
+            self.partial_dirs = None;
         }
-        
+    }
+
+    fn entries(&mut self) -> Result<read::Entries<'_>> {
+        self.dirs().map(read::Entries::dirs)
+    }
+
+    fn list(&mut self) -> Result<Vec<read::Dir>> {
+        self.dirs().map(|dirs| dirs.to_vec())
+    }
+
+    fn extract<W: Write>(&mut self, file: &read::File, mut writer: W) -> Result<()> {
+        // the size of the data block includes the embedded name and the uncompressed size field.
+        let mut size = self.seek_data(file)?;
+
         if file.compressed {
             // skip uncompressed size field
             self.reader.seek(SeekFrom::Current(size_of::<u32>() as i64))?;
+            size = size.saturating_sub(size_of::<u32>() as u64);
 
-            let sub_reader = (&mut self.reader).take(file.size as u64);
+            let sub_reader = (&mut self.reader).take(size);
             C::uncompress(sub_reader, writer)?;
         } else {
-            let mut sub_reader = (&mut self.reader).take(file.size as u64);
+            let mut sub_reader = (&mut self.reader).take(size);
             copy(&mut sub_reader, &mut writer)?;
         }
         Ok(())
     }
+
+    /// Compressed files store their uncompressed size in front of the data.
+    fn uncompressed_size(&mut self, file: &read::File) -> Result<u64> {
+        let size = self.seek_data(file)?;
+        if file.compressed {
+            let size: u32 = read_struct(&mut self.reader)?;
+            Ok(self.native(size) as u64)
+        } else {
+            Ok(size)
+        }
+    }
 }
 
 
@@ -336,6 +494,15 @@ pub struct DirRecord {
 derive_var_size_via_size_of!(DirRecord);
 derive_readable_via_pod!(DirRecord);
 derive_writable_via_pod!(DirRecord);
+impl SwapBytes for DirRecord {
+    fn swap_bytes(self) -> Self {
+        Self {
+            name_hash: self.name_hash.swap_bytes(),
+            file_count: self.file_count.swap_bytes(),
+            offset: self.offset.swap_bytes(),
+        }
+    }
+}
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
@@ -357,6 +524,15 @@ impl FileRecord {
 derive_var_size_via_size_of!(FileRecord);
 derive_readable_via_pod!(FileRecord);
 derive_writable_via_pod!(FileRecord);
+impl SwapBytes for FileRecord {
+    fn swap_bytes(self) -> Self {
+        Self {
+            name_hash: self.name_hash.swap_bytes(),
+            size: self.size.swap_bytes(),
+            offset: self.offset.swap_bytes(),
+        }
+    }
+}
 
 
 #[derive(Debug)]
@@ -391,6 +567,18 @@ impl Writable for DirContentRecord {
     }
 }
 
+#[derive(Debug, Error)]
+#[error("v10x requires unique hashes. {0} and {1} have the same hash: {2}")]
+pub struct HashCollision(pub String, pub String, pub Hash);
+
+type ContentHash = [u8; 32];
+
+fn content_hash<D: DataSource>(data: &D) -> Result<ContentHash> {
+    let mut hasher = Sha256::new();
+    copy(&mut data.open()?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
 struct FileNames {
     size: u32,
     values: Vec<ZString>,
@@ -403,13 +591,18 @@ pub struct WriterV10X<T, C, AF: BitFlag, RDR> {
     phantom_rdr: PhantomData<RDR>,
     pub archive_flags: BitFlags<AF>,
     pub file_flags: BitFlags<FileFlag>,
+    /// The codepage names are encoded with.
+    pub codepage: Codepage,
+    /// Store the data of byte identical files only once.
+    /// Has no effect if file names are embedded, since every data block starts with the path of its file.
+    pub deduplicate: bool,
 }
 impl<T, C, AF, RDR> WriterV10X<T, C, AF, RDR>
 where
     T: Versioned,
     C: Compression,
     AF: ToArchiveBitFlags,
-    RDR: From<DirRecord> + Into<DirRecord> + Writable + Sized + Copy
+    RDR: From<DirRecord> + Into<DirRecord> + SwapBytes + Writable + Sized + Copy
 {
     pub fn new<A, F>(archive_flags: A,file_flags: F) -> Self
     where
@@ -423,6 +616,57 @@ where
             phantom_c: PhantomData,
             archive_flags: archive_flags.into_iter().collect(),
             file_flags: file_flags.into_iter().collect(),
+            codepage: Codepage::default(),
+            deduplicate: false,
+        }
+    }
+
+    /// Write an archive and report how much space was saved by deduplication.
+    pub fn write_bsa_with_stats<DS, D, W>(&self, raw_dirs: DS, mut out: W, observer: &mut dyn Observer) -> Result<write::WriteStats>
+    where
+        DS: IntoIterator<Item = write::Dir<D>>,
+        D: DataSource,
+        W: Write + Seek,
+    {
+        let dirs: Vec<write::Dir<D>> = raw_dirs.into_iter().collect();
+        self.check_hashes(&dirs)?;
+        observer.started(write::totals(&dirs));
+        Self::write_version(&mut out)?;
+        let file_names = self.write_header(&dirs, &mut out)?;
+        let mut dir_records = self.write_dir_records(&dirs, &mut out)?;
+        let mut dir_content_records = self.write_dir_content_records(&dirs, &mut dir_records, file_names.size, &mut out)?;
+        file_names.values.write(&mut out)?;
+        self.write_file_contents(&dirs, &mut dir_content_records, &mut out, observer)
+    }
+
+    /// Directories and the files of each directory are looked up by their hashes, so they have to be unique.
+    fn check_hashes<D>(&self, dirs: &[write::Dir<D>]) -> Result<()> {
+        let mut dir_names: HashMap<Hash, &str> = HashMap::new();
+        for dir in dirs {
+            let hash = self.codepage.hash_v10x(&dir.name)?;
+            if let Some(other) = dir_names.insert(hash, &dir.name) {
+                return Err(hash_collision(other, &dir.name, hash));
+            }
+            let mut file_names: HashMap<Hash, &str> = HashMap::new();
+            for file in &dir.files {
+                let hash = self.codepage.hash_v10x(&file.name)?;
+                if let Some(other) = file_names.insert(hash, &file.name) {
+                    return Err(hash_collision(
+                        &format!("{}\\{}", dir.name, other),
+                        &format!("{}\\{}", dir.name, file.name),
+                        hash));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records of Xbox 360 archives are big-endian.
+    fn stored<A: SwapBytes>(&self, record: A) -> A {
+        if self.has(AF::xbox_archive()) {
+            record.swap_bytes()
+        } else {
+            record
         }
     }
 
@@ -442,12 +686,12 @@ where
             header.file_count += dir.files.len() as u32;
             
             if self.has(AF::includes_dir_names()) {
-                header.total_dir_name_length += (dir.name.len() as u32) + 1;
+                header.total_dir_name_length += (self.codepage.encode(&dir.name)?.len() as u32) + 1;
             }
             
             if self.has(AF::includes_file_names()) {
                 for file in dir.files.iter() {
-                    let file_name = ZString::from_str(&file.name.to_lowercase())?;
+                    let file_name = ZString::encode(&file.name.to_ascii_lowercase(), self.codepage)?;
                     file_names.push(file_name);
                 }
             }
@@ -465,42 +709,42 @@ where
         })
     }
 
-    fn write_dir_record<W, D>(dir: &write::Dir<D>, out: W) -> Result<Positioned<RDR>>
+    fn write_dir_record<W, D>(&self, dir: &write::Dir<D>, out: W) -> Result<Positioned<RDR>>
     where W: Write + Seek {
         let rec = DirRecord {
-            name_hash: Hash::v10x(&dir.name),
+            name_hash: self.codepage.hash_v10x(&dir.name)?,
             file_count: dir.files.len() as u32,
             offset: 0,
         };
-        Positioned::new(RDR::from(rec), out)
+        Positioned::new(self.stored(RDR::from(rec)), out)
     }
 
-    fn write_dir_records<W, D>(dirs: &Vec<write::Dir<D>>, mut out: W) -> Result<Vec<Positioned<RDR>>>
+    fn write_dir_records<W, D>(&self, dirs: &Vec<write::Dir<D>>, mut out: W) -> Result<Vec<Positioned<RDR>>>
     where W: Write + Seek {
         dirs.iter()
-            .map(|dir| Self::write_dir_record(dir, &mut out))
+            .map(|dir| self.write_dir_record(dir, &mut out))
             .collect()
     }
 
     fn write_dir_content_record<W, D>(&self, dir: &write::Dir<D>, out: W) -> Result<Positioned<DirContentRecord>>
     where W: Write + Seek {
         let name = if self.has(AF::includes_dir_names()) {
-            let s = BZString::new(dir.name.to_lowercase())?;
+            let s = BZString::encode(&dir.name.to_ascii_lowercase(), self.codepage)?;
             Some(s)
         } else {
             None
         };
         let files = dir.files.iter()
-            .map(|file| FileRecord {
-                name_hash: Hash::v10x(&file.name),
+            .map(|file| Ok(FileRecord {
+                name_hash: self.codepage.hash_v10x(&file.name)?,
                 size: if file.compressed == Some(!self.has(AF::is_compressed_by_default())) {
                     0x40000000
                 } else {
                     0
                 },
                 offset: 0,
-            })
-            .collect();
+            }))
+            .collect::<Result<Vec<FileRecord>>>()?;
         Positioned::new(DirContentRecord { name, files }, out)
     }
 
@@ -516,9 +760,9 @@ where
             .map(|(dir, mut pdr)| {
                 let fcr = self.write_dir_content_record(dir, &mut out)?;
 
-                let mut dr: DirRecord = pdr.data.into();
+                let mut dr: DirRecord = self.stored(pdr.data).into();
                 dr.offset = fcr.position as u32 + total_file_name_length;
-                pdr.data = RDR::from(dr);
+                pdr.data = self.stored(RDR::from(dr));
                 pdr.update(&mut out)?;
                 
                 Ok(fcr)
@@ -526,34 +770,37 @@ where
             .collect()
     }
 
-    fn write_embeded_file_name<W>(dir: &String, file: &String, out: W) -> Result<()>
+    fn write_embeded_file_name<W>(&self, dir: &String, file: &String, out: W) -> Result<()>
     where W: Write + Seek {
         let path = &format!("{}\\{}",
             dir.replace("/", "\\"),
             file.replace("/", "\\"));
-        BString::from_str(path)?
+        BString::encode(path, self.codepage)?
             .write(out)
     }
 
-    fn write_file_content<W, D>(&self, dir: &write::Dir<D>, file: &write::File<D>, mut out: W) -> Result<u64>
+    fn write_file_content<W, D>(&self, dir: &write::Dir<D>, file: &write::File<D>, mut out: W, observer: &mut dyn Observer) -> Result<u64>
     where
         W: Write + Seek,
         D: DataSource,
     {
+        let start = out.stream_position()?;
         let is_compressed_by_default = self.has(AF::is_compressed_by_default());
         if self.has_any(&AF::embed_file_names()) {
-            Self::write_embeded_file_name(&dir.name, &file.name, &mut out)?;
+            self.write_embeded_file_name(&dir.name, &file.name, &mut out)?;
         }
-        let mut data_source = file.data.open()?;
+        let mut data_source = ObservedRead {
+            inner: file.data.open()?,
+            observer,
+        };
         if file.compressed.unwrap_or(is_compressed_by_default) {
             let mut size_orig: Positioned<u32> = Positioned::new_empty(&mut out)?;
-            size_orig.data = C::compress(data_source, &mut out)? as u32;
+            size_orig.data = self.stored(C::compress(data_source, &mut out)? as u32);
             size_orig.update(&mut out)?;
-            
-            Ok(out.stream_position()? - size_orig.position)
         } else {
-            copy(&mut data_source, &mut out)
+            copy(&mut data_source, &mut out)?;
         }
+        Ok(out.stream_position()? - start)
     }
 
     fn write_file_contents<W, D: DataSource>(
@@ -561,17 +808,46 @@ where
         dirs: &Vec<write::Dir<D>>,
         dir_content_records: &mut Vec<Positioned<DirContentRecord>>,
         mut out: W,
-    ) -> Result<()>
+        observer: &mut dyn Observer,
+    ) -> Result<write::WriteStats>
     where W: Write + Seek {
+        let deduplicate = self.deduplicate && !self.has_any(&AF::embed_file_names());
+        let is_compressed_by_default = self.has(AF::is_compressed_by_default());
+        let mut stats = write::WriteStats::default();
+        let mut written: HashMap<(ContentHash, bool), (u32, u32)> = HashMap::new();
         for (dir, pfcr) in dirs.iter().zip(dir_content_records) {
             
             for (file, mut fr) in dir.files.iter().zip(&mut pfcr.data.files) {
-                fr.offset = out.stream_position()? as u32;
-                fr.size |= self.write_file_content(dir, file, &mut out)? as u32;
+                check_cancelled(observer)?;
+                let path = format!("{}\\{}", dir.name, file.name);
+                observer.file_started(&path, file.data.size());
+                let key = if deduplicate {
+                    Some((content_hash(&file.data)?, file.compressed.unwrap_or(is_compressed_by_default)))
+                } else {
+                    None
+                };
+                if let Some((offset, size)) = key.and_then(|key| written.get(&key)) {
+                    fr.offset = *offset;
+                    fr.size |= *size;
+                    stats.duplicates += 1;
+                    stats.bytes_saved += *size as u64;
+                    observer.bytes(file.data.size().unwrap_or(0));
+                } else {
+                    fr.offset = out.stream_position()? as u32;
+                    let size = self.write_file_content(dir, file, &mut out, &mut *observer)? as u32;
+                    fr.size |= size;
+                    if let Some(key) = key {
+                        written.insert(key, (fr.offset, size));
+                    }
+                }
+                observer.file_finished(&path);
+            }
+            for fr in &mut pfcr.data.files {
+                *fr = self.stored(*fr);
             }
             pfcr.update(&mut out)?;
         }
-        Ok(())
+        Ok(stats)
     }
    
 }
@@ -580,7 +856,7 @@ where
     T: Versioned,
     C: Compression,
     AF: ToArchiveBitFlags,
-    RDR: From<DirRecord> + Into<DirRecord> + Writable + Sized + Copy
+    RDR: From<DirRecord> + Into<DirRecord> + SwapBytes + Writable + Sized + Copy
 {
     fn default() -> Self {
         Self::new([AF::includes_file_names(), AF::includes_dir_names()], [])
@@ -610,21 +886,16 @@ where
     T: Versioned,
     C: Compression,
     AF: ToArchiveBitFlags,
-    RDR: From<DirRecord> + Into<DirRecord> + Writable + Sized + Copy + fmt::Debug
+    RDR: From<DirRecord> + Into<DirRecord> + SwapBytes + Writable + Sized + Copy + fmt::Debug
 {
-    fn write_bsa<DS, D, W>(&self, raw_dirs: DS, mut out: W) -> Result<()>
+    fn write_bsa_observed<DS, D, W>(&self, raw_dirs: DS, out: W, observer: &mut dyn Observer) -> Result<()>
     where
         DS: IntoIterator<Item = write::Dir<D>>,
         D: DataSource,
         W: Write + Seek,
     {
-        let dirs: Vec<write::Dir<D>> = raw_dirs.into_iter().collect();
-        Self::write_version(&mut out)?;
-        let file_names = self.write_header(&dirs, &mut out)?;
-        let mut dir_records = Self::write_dir_records(&dirs, &mut out)?;
-        let mut dir_content_records = self.write_dir_content_records(&dirs, &mut dir_records, file_names.size, &mut out)?;
-        file_names.values.write(&mut out)?;
-        self.write_file_contents(&dirs, &mut dir_content_records, &mut out)
+        self.write_bsa_with_stats(raw_dirs, out, observer)
+            .map(|_| ())
     }
 }
 
@@ -632,7 +903,8 @@ where
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
-    use crate::v105::{HeaderV105, ArchiveFlagV105};
+    use crate::v105::{HeaderV105, ArchiveFlagV105, ReaderV105, WriterV105};
+    use crate::write::test::*;
     use super::*;
 
     #[test]
@@ -662,4 +934,144 @@ mod tests {
         
         Ok(())
     }
+
+    #[test]
+    fn embedded_names_replace_missing_name_tables() {
+        for compressed in [false, true] {
+            let mut dirs = vec![
+                write::Dir::new("a\\b", [
+                    write::File::new("c", vec![1, 2, 3, 4]),
+                    write::File::new("d", vec![5, 6]),
+                ]),
+            ];
+            dirs[0].files[0].compressed = Some(compressed);
+            let writer = WriterV105::new([ArchiveFlagV105::EmbedFileNames], []);
+            let bytes = bsa_bytes(writer, dirs.clone());
+            let mut bsa = ReaderV105::read_bsa(bytes)
+                .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+            let in_dirs = bsa.list()
+                .unwrap_or_else(|err| panic!("could not read dirs {}", err));
+
+            assert_eq!(in_dirs[0].id.name, Some("a\\b".to_owned()), "in_dirs[0].id.name");
+            for (file, expected) in in_dirs[0].files.iter().zip(&dirs[0].files) {
+                assert_eq!(file.id.name, Some(expected.name.clone()), "file.id.name");
+                assert_eq!(file.embedded_name, Some(format!("a\\b\\{}", expected.name)), "file.embedded_name");
+
+                let mut data = Vec::<u8>::new();
+                bsa.extract(file, &mut data)
+                    .unwrap_or_else(|err| panic!("could not extract data {}", err));
+                assert_eq!(expected.data, data, "file data");
+            }
+        }
+    }
+    #[test]
+    fn xbox_archives_are_big_endian() {
+        let dirs = || vec![
+            write::Dir::new("a\\b", [write::File::new("c.nif", vec![1; 100]), write::File::new("d", vec![5, 6])]),
+            write::Dir::new("e", [write::File::new("f.dds", vec![7])]),
+        ];
+        let mut v103 = crate::WriterV103::default();
+        v103.archive_flags |= crate::ArchiveFlagV103::Xbox360Archive | crate::ArchiveFlagV103::CompressedArchive;
+        let mut v104 = crate::WriterV104::default();
+        v104.archive_flags |= crate::ArchiveFlagV104::Xbox360Archive | crate::ArchiveFlagV104::CompressedArchive | crate::ArchiveFlagV104::EmbedFileNames;
+        let mut v105 = WriterV105::default();
+        v105.archive_flags |= ArchiveFlagV105::Xbox360Archive | ArchiveFlagV105::CompressedArchive;
+        for bytes in [bsa_bytes(v103, dirs()), bsa_bytes(v104, dirs()), bsa_bytes(v105, dirs())] {
+            let first_dir_hash = &bytes.get_ref()[36..44];
+            let hash = Hash::v10x("a\\b");
+            assert_eq!(first_dir_hash.iter().rev().copied().collect::<Vec<u8>>(), bytemuck::bytes_of(&hash), "big-endian hash");
+
+            let mut bsa = crate::SomeReader::read_bsa(bytes)
+                .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+            let entries: Vec<(String, read::File)> = bsa.entries()
+                .unwrap_or_else(|err| panic!("could not read entries {}", err))
+                .map(|(dir, file)| (read::path(dir, file), file.clone()))
+                .collect();
+            let paths: Vec<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+            assert_eq!(paths, vec!["a/b/c.nif", "a/b/d", "e/f.dds"], "paths");
+            for ((path, file), expected) in entries.iter().zip(dirs().iter().flat_map(|dir| dir.files.clone())) {
+                assert!(file.compressed, "{} compressed", path);
+                let mut data = Vec::new();
+                bsa.extract(file, &mut data)
+                    .unwrap_or_else(|err| panic!("could not extract {} {}", path, err));
+                assert_eq!(data, expected.data, "data of {}", path);
+                let size = bsa.uncompressed_size(file)
+                    .unwrap_or_else(|err| panic!("could not read size of {} {}", path, err));
+                assert_eq!(size, expected.data.len() as u64, "size of {}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn entries_borrow_index() {
+        let dirs = vec![
+            write::Dir::new("a", [write::File::new("b", vec![1]), write::File::new("c", vec![2])]),
+            write::Dir::new("d", [write::File::new("e", vec![3])]),
+        ];
+        let mut bsa = ReaderV105::read_bsa(bsa_bytes(WriterV105::default(), dirs))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+
+        let paths: Vec<String> = bsa.entries()
+            .unwrap_or_else(|err| panic!("could not read entries {}", err))
+            .map(|(dir, file)| format!("{}/{}", dir.map_or(String::new(), |dir| dir.id.to_string()), file.id))
+            .collect();
+        assert_eq!(paths, vec!["a/b", "a/c", "d/e"], "entries");
+
+        let listed = bsa.list()
+            .unwrap_or_else(|err| panic!("could not read dirs {}", err));
+        assert_eq!(listed.len(), 2, "listed.len()");
+    }
+    #[test]
+    fn deduplicate_identical_data() {
+        let dirs = vec![
+            write::Dir::new("a", [
+                write::File::new("b", vec![1, 2, 3, 4]),
+                write::File::new("c", vec![1, 2, 3, 4]),
+                write::File::new("d", vec![5]),
+            ]),
+        ];
+        let mut writer = WriterV105::default();
+        writer.deduplicate = true;
+        let mut out = Cursor::new(Vec::<u8>::new());
+        let stats = writer.write_bsa_with_stats(dirs.clone(), &mut out, &mut ())
+            .unwrap_or_else(|err| panic!("could not write bsa {}", err));
+        assert_eq!(stats.duplicates, 1, "stats.duplicates");
+        assert_eq!(stats.bytes_saved, 4, "stats.bytes_saved");
+
+        let mut bsa = ReaderV105::read_bsa(Cursor::new(out.into_inner()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let in_dirs = bsa.list()
+            .unwrap_or_else(|err| panic!("could not read dirs {}", err));
+        assert_eq!(in_dirs[0].files[0].offset, in_dirs[0].files[1].offset, "shared offset");
+        for (file, expected) in in_dirs[0].files.iter().zip(&dirs[0].files) {
+            let mut data = Vec::<u8>::new();
+            bsa.extract(file, &mut data)
+                .unwrap_or_else(|err| panic!("could not extract data {}", err));
+            assert_eq!(expected.data, data, "file data");
+        }
+    }
+
+    #[test]
+    fn names_without_stem_hash_by_extension() {
+        assert_eq!(Hash::v10x("").to_string(), "0000000000000000", "root dir");
+        assert_ne!(Hash::v10x(".alpha"), Hash::v10x(".beta"), ".alpha and .beta");
+        assert_ne!(Hash::v10x(".alpha"), Hash::v10x(""), ".alpha");
+    }
+    #[test]
+    fn reject_hash_collisions() {
+        let dirs = vec![
+            write::Dir::new("a", [write::File::new("b", vec![1]), write::File::new("B", vec![2])]),
+        ];
+        let mut out = Cursor::new(Vec::<u8>::new());
+        let err = WriterV105::default().write_bsa(dirs, &mut out)
+            .err()
+            .unwrap_or_else(|| panic!("wrote colliding files"));
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "kind");
+
+        let dirs = vec![
+            write::Dir::new("a", [write::File::new("b", vec![1])]),
+            write::Dir::new("A", [write::File::new("c", vec![2])]),
+        ];
+        assert!(WriterV105::default().write_bsa(dirs, &mut out).is_err(), "wrote colliding dirs");
+    }
 }
@@ -141,6 +141,33 @@ impl Version {
         }
     }
 }
+/// Serialized by name like `v105`. BA2 archives are serialized as `ba2` and can't be deserialized.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Version {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            Version::V001 => "v001",
+            Version::V10X(Version10X::V103) => "v103",
+            Version::V10X(Version10X::V104) => "v104",
+            Version::V10X(Version10X::V105) => "v105",
+            Version::BA2(_, _) => "ba2",
+        })
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Version {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, Unexpected};
+        let name = String::deserialize(deserializer)?;
+        match name.to_lowercase().trim_start_matches('v') {
+            "001" => Ok(Version::V001),
+            "103" => Ok(Version::V10X(Version10X::V103)),
+            "104" => Ok(Version::V10X(Version10X::V104)),
+            "105" => Ok(Version::V10X(Version10X::V105)),
+            _ => Err(D::Error::invalid_value(Unexpected::Str(&name), &"one of v001, v103, v104 or v105")),
+        }
+    }
+}
 impl From<&Version> for MagicNumber {
     fn from(version: &Version) -> MagicNumber {
         match version {
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::{Codepage, EntryId, Hash, SomeReader, SomeRoot};
+use crate::read::{self, Reader};
+use crate::scan::Scanner;
+
+
+#[derive(Debug, Error)]
+#[error("{0} not found")]
+pub struct NotFound(pub String);
+
+/// Where the data of a file comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// A file inside the archive at the given position in the load order.
+    Archive(usize, read::File),
+    /// A loose file in the data directory.
+    Loose(PathBuf),
+}
+
+/// The sources providing a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolved<'a> {
+    /// The source the game actually loads.
+    pub winner: &'a Source,
+    /// All sources overridden by the winner, from highest to lowest priority.
+    pub overridden: &'a [Source],
+}
+
+/// An entry of a directory returned by [`Vfs::read_dir`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DirEntry<'a> {
+    /// A subdirectory by its name relative to the directory.
+    Dir(String),
+    File(&'a EntryId),
+}
+
+/// A directory of the merged index.
+#[derive(Clone, Debug, Default)]
+struct VfsDir {
+    name: Option<String>,
+    files: BTreeMap<Hash, EntryId>,
+}
+
+type Key = (Hash, Hash);
+
+/// A virtual filesystem merging multiple archives and loose files the way the games do.
+/// Archives are added in load order, later archives override earlier ones.
+/// Loose files in the data directory override all archives.
+///
+/// Paths are looked up by their directory and file [`Hash`], so entries of archives
+/// without names can be resolved as well.
+pub struct Vfs<R> {
+    archives: Vec<SomeReader<R>>,
+    codepage: Codepage,
+    dirs: HashMap<Hash, VfsDir>,
+    /// Sources of each file ordered from lowest to highest priority.
+    files: HashMap<Key, Vec<Source>>,
+}
+impl<R: Read + Seek> Vfs<R> {
+    pub fn new() -> Self {
+        Self::with_codepage(Codepage::default())
+    }
+
+    /// Create an empty filesystem hashing paths encoded with the given codepage.
+    pub fn with_codepage(codepage: Codepage) -> Self {
+        Self {
+            archives: Vec::new(),
+            codepage,
+            dirs: HashMap::new(),
+            files: HashMap::new(),
+        }
+    }
+
+    /// The archives in load order.
+    pub fn archives(&self) -> &[SomeReader<R>] {
+        &self.archives
+    }
+
+    /// Add the next archive of the load order.
+    /// Its files override files of all previously added archives.
+    pub fn push_archive(&mut self, mut archive: SomeReader<R>) -> io::Result<()> {
+        archive.set_codepage(self.codepage);
+        let index = self.archives.len();
+        match archive.list()? {
+            SomeRoot::V001(files) => {
+                for file in files {
+                    let path = normalize(file.id.name.as_deref().unwrap_or_default());
+                    let (dir, name) = split(&path);
+                    let dir_id = self.id(dir);
+                    let file_id = self.id(name);
+                    self.insert(dir_id, file_id, Source::Archive(index, file));
+                }
+            },
+            SomeRoot::V10X(dirs) => {
+                for dir in dirs {
+                    for file in &dir {
+                        self.insert(dir.id.clone(), file.id.clone(), Source::Archive(index, file.clone()));
+                    }
+                }
+            },
+        }
+        self.archives.push(archive);
+        Ok(())
+    }
+
+    /// Add all files below the data directory as loose files.
+    /// Hidden files and backups are included, since the game loads them as well.
+    pub fn add_data_dir<P: AsRef<Path>>(&mut self, data_dir: P) -> io::Result<()> {
+        let mut scanner = Scanner::new();
+        scanner.hidden = true;
+        scanner.backups = true;
+        for dir in scanner.scan(data_dir)? {
+            let dir_id = self.id(&normalize(&dir.name));
+            for file in dir.files {
+                let file_id = self.id(&normalize(&file.name));
+                self.insert(dir_id.clone(), file_id, Source::Loose(file.data));
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the source the game loads for a path and all sources it overrides.
+    pub fn resolve<S: AsRef<str>>(&self, path: S) -> Option<Resolved<'_>> {
+        let sources = self.files.get(&self.key(path.as_ref())?)?;
+        let (winner, overridden) = sources.split_last()?;
+        Some(Resolved { winner, overridden })
+    }
+
+    pub fn exists<S: AsRef<str>>(&self, path: S) -> bool {
+        self.resolve(path).is_some()
+    }
+
+    /// All directories. Names of directories are only known if any source provides one.
+    pub fn dirs(&self) -> impl Iterator<Item = EntryId> + '_ {
+        self.dirs.iter()
+            .map(|(hash, dir)| EntryId { hash: *hash, name: dir.name.clone() })
+    }
+
+    /// The subdirectories and files directly inside a directory, subdirectories first.
+    /// Subdirectories are only found if any source provides their name.
+    /// Returns `None` if there is neither.
+    pub fn read_dir<S: AsRef<str>>(&self, path: S) -> Option<Vec<DirEntry<'_>>> {
+        let path = normalize(path.as_ref());
+        let prefix = if path.is_empty() { String::new() } else { format!("{}\\", path) };
+        let sub_dirs: BTreeSet<&str> = self.dirs.values()
+            .filter_map(|dir| dir.name.as_deref())
+            .filter_map(|name| name.strip_prefix(prefix.as_str()))
+            .filter_map(|rest| rest.split('\\').next())
+            .filter(|name| !name.is_empty())
+            .collect();
+        let files = self.dirs.get(&self.hash(&path)).map(|dir| &dir.files);
+        if sub_dirs.is_empty() && files.map_or(true, BTreeMap::is_empty) {
+            return None;
+        }
+        Some(sub_dirs.into_iter()
+            .map(|name| DirEntry::Dir(name.to_owned()))
+            .chain(files.into_iter().flat_map(BTreeMap::values).map(DirEntry::File))
+            .collect())
+    }
+
+    /// Open the winning source of a path.
+    pub fn open<S: AsRef<str>>(&mut self, path: S) -> io::Result<VfsFile> {
+        let path = path.as_ref();
+        match self.resolve(path).map(|res| res.winner.clone()) {
+            Some(Source::Loose(file)) => Ok(VfsFile::Loose(fs::File::open(file)?)),
+            Some(Source::Archive(index, file)) => {
+                let mut data = Vec::with_capacity(file.size);
+                self.archives[index].extract(&file, &mut data)?;
+                Ok(VfsFile::Archive(Cursor::new(data)))
+            },
+            None => Err(io::Error::new(ErrorKind::NotFound, NotFound(path.to_owned()))),
+        }
+    }
+
+    /// Write the content of the winning source of a path.
+    pub fn extract<S: AsRef<str>, W: Write>(&mut self, path: S, mut out: W) -> io::Result<()> {
+        let mut file = self.open(path)?;
+        io::copy(&mut file, &mut out)?;
+        Ok(())
+    }
+
+    fn insert(&mut self, dir_id: EntryId, file_id: EntryId, source: Source) {
+        let dir = self.dirs.entry(dir_id.hash).or_default();
+        if dir.name.is_none() {
+            dir.name = dir_id.name;
+        }
+        let known = dir.files.entry(file_id.hash).or_insert_with(|| file_id.clone());
+        if known.name.is_none() {
+            known.name = file_id.name;
+        }
+
+        let sources = self.files.entry((dir_id.hash, file_id.hash)).or_default();
+        let pos = match source {
+            Source::Loose(_) => sources.len(),
+            Source::Archive(..) => sources.iter()
+                .position(|s| matches!(s, Source::Loose(_)))
+                .unwrap_or(sources.len()),
+        };
+        sources.insert(pos, source);
+    }
+
+    fn key(&self, path: &str) -> Option<Key> {
+        let path = normalize(path);
+        let (dir, name) = split(&path);
+        if name.is_empty() {
+            None
+        } else {
+            Some((self.hash(dir), self.hash(name)))
+        }
+    }
+
+    fn id(&self, name: &str) -> EntryId {
+        EntryId {
+            hash: self.hash(name),
+            name: Some(name.to_owned()),
+        }
+    }
+
+    fn hash(&self, name: &str) -> Hash {
+        self.codepage.hash_v10x(name)
+            .unwrap_or_else(|_| Hash::v10x(name))
+    }
+}
+impl<R: Read + Seek> Default for Vfs<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The content of a file opened from a [`Vfs`].
+pub enum VfsFile {
+    Loose(fs::File),
+    Archive(Cursor<Vec<u8>>),
+}
+impl Read for VfsFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            VfsFile::Loose(file) => file.read(buf),
+            VfsFile::Archive(data) => data.read(buf),
+        }
+    }
+}
+
+fn normalize(path: &str) -> String {
+    path.to_ascii_lowercase()
+        .replace('/', "\\")
+        .trim_matches('\\')
+        .to_owned()
+}
+
+/// Split a path into directory and file name.
+/// Files at the root are in the directory with the empty name.
+fn split(path: &str) -> (&str, &str) {
+    path.rsplit_once('\\')
+        .unwrap_or(("", path))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::{ForSomeVersion, ForSomeVersion10X, WriterV001, WriterV105};
+    use crate::v001::ReaderV001;
+    use crate::v105::ReaderV105;
+    use crate::write::{Dir, File, test::*};
+    use super::*;
+
+    fn archive_v105(dirs: Vec<Dir<Vec<u8>>>) -> SomeReader<Cursor<Vec<u8>>> {
+        let bsa = ReaderV105::read_bsa(bsa_bytes(WriterV105::default(), dirs))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        ForSomeVersion::V10X(ForSomeVersion10X::V105(bsa))
+    }
+
+    fn archive_v001(dirs: Vec<Dir<Vec<u8>>>) -> SomeReader<Cursor<Vec<u8>>> {
+        let bsa = ReaderV001::read_bsa(bsa_bytes(WriterV001::default(), dirs))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        ForSomeVersion::V001(bsa)
+    }
+
+    fn read(vfs: &mut Vfs<Cursor<Vec<u8>>>, path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        vfs.extract(path, &mut data)
+            .unwrap_or_else(|err| panic!("could not extract {} {}", path, err));
+        data
+    }
+
+    #[test]
+    fn later_archives_win() {
+        let mut vfs = Vfs::new();
+        vfs.push_archive(archive_v105(vec![
+            Dir::new("meshes", [File::new("a.nif", vec![1]), File::new("b.nif", vec![2])]),
+        ])).unwrap_or_else(|err| panic!("could not add archive {}", err));
+        vfs.push_archive(archive_v001(vec![
+            Dir::new("meshes", [File::new("a.nif", vec![3])]),
+        ])).unwrap_or_else(|err| panic!("could not add archive {}", err));
+
+        let resolved = vfs.resolve("Meshes/A.nif")
+            .unwrap_or_else(|| panic!("could not resolve path"));
+        assert!(matches!(resolved.winner, Source::Archive(1, _)), "winner");
+        assert_eq!(resolved.overridden.len(), 1, "overridden");
+        assert!(vfs.exists("meshes\\b.nif"), "exists b.nif");
+        assert!(!vfs.exists("meshes\\c.nif"), "exists c.nif");
+        assert_eq!(read(&mut vfs, "meshes/a.nif"), vec![3], "a.nif");
+        assert_eq!(read(&mut vfs, "meshes/b.nif"), vec![2], "b.nif");
+
+        let files = vfs.read_dir("meshes")
+            .unwrap_or_else(|| panic!("could not read dir"));
+        assert_eq!(files.len(), 2, "files in dir");
+    }
+
+    #[test]
+    fn non_ascii_names_keep_their_case() {
+        let mut vfs = Vfs::new();
+        vfs.push_archive(archive_v105(vec![
+            Dir::new("textures", [File::new("Äpfel.dds", vec![1])]),
+        ])).unwrap_or_else(|err| panic!("could not add archive {}", err));
+
+        assert!(vfs.exists("textures/Äpfel.dds"), "exists Äpfel.dds");
+        assert!(vfs.exists("TEXTURES\\Äpfel.DDS"), "exists with ascii uppercase");
+        assert!(!vfs.exists("textures/äpfel.dds"), "exists with non-ascii lowercase");
+        assert_eq!(read(&mut vfs, "textures/Äpfel.dds"), vec![1], "Äpfel.dds");
+    }
+
+    #[test]
+    fn read_dir_lists_sub_dirs() {
+        let mut vfs = Vfs::new();
+        vfs.push_archive(archive_v105(vec![
+            Dir::new("meshes\\armor\\iron", [File::new("a.nif", vec![1])]),
+            Dir::new("meshes\\clutter", [File::new("b.nif", vec![2])]),
+            Dir::new("textures", [File::new("c.dds", vec![3])]),
+        ])).unwrap_or_else(|err| panic!("could not add archive {}", err));
+
+        let entries = vfs.read_dir("Meshes")
+            .unwrap_or_else(|| panic!("could not read meshes"));
+        assert_eq!(entries, vec![DirEntry::Dir("armor".to_owned()), DirEntry::Dir("clutter".to_owned())], "meshes");
+        let entries = vfs.read_dir("meshes/armor")
+            .unwrap_or_else(|| panic!("could not read meshes/armor"));
+        assert_eq!(entries, vec![DirEntry::Dir("iron".to_owned())], "meshes/armor");
+        let entries = vfs.read_dir("meshes/clutter")
+            .unwrap_or_else(|| panic!("could not read meshes/clutter"));
+        assert!(matches!(entries.as_slice(), [DirEntry::File(id)] if id.name.as_deref() == Some("b.nif")), "meshes/clutter");
+        let entries = vfs.read_dir("")
+            .unwrap_or_else(|| panic!("could not read root"));
+        assert_eq!(entries, vec![DirEntry::Dir("meshes".to_owned()), DirEntry::Dir("textures".to_owned())], "root");
+        assert_eq!(vfs.read_dir("sounds"), None, "missing dir");
+    }
+
+    #[test]
+    fn loose_files_win() {
+        let data_dir = temp_dir("bsa-vfs-loose-files-win");
+        fs::create_dir_all(data_dir.join("meshes"))
+            .unwrap_or_else(|err| panic!("could not create data dir {}", err));
+        fs::write(data_dir.join("meshes").join("a.nif"), [9])
+            .unwrap_or_else(|err| panic!("could not write loose file {}", err));
+
+        let mut vfs = Vfs::new();
+        vfs.add_data_dir(&data_dir)
+            .unwrap_or_else(|err| panic!("could not add data dir {}", err));
+        vfs.push_archive(archive_v105(vec![
+            Dir::new("meshes", [File::new("a.nif", vec![1])]),
+        ])).unwrap_or_else(|err| panic!("could not add archive {}", err));
+
+        let data = read(&mut vfs, "meshes/a.nif");
+        fs::remove_dir_all(&data_dir)
+            .unwrap_or_else(|err| panic!("could not remove data dir {}", err));
+        assert_eq!(data, vec![9], "a.nif");
+    }
+
+    #[test]
+    fn loose_hidden_files_and_backups() {
+        let data_dir = temp_dir("bsa-vfs-loose-hidden-files");
+        fs::create_dir_all(data_dir.join(".meshes"))
+            .unwrap_or_else(|err| panic!("could not create data dir {}", err));
+        for name in [".meshes/a.nif", "b.nif~", "c.bak"] {
+            fs::write(data_dir.join(name), [9])
+                .unwrap_or_else(|err| panic!("could not write loose file {}", err));
+        }
+
+        let mut vfs = Vfs::<Cursor<Vec<u8>>>::new();
+        let res = vfs.add_data_dir(&data_dir);
+        fs::remove_dir_all(&data_dir)
+            .unwrap_or_else(|err| panic!("could not remove data dir {}", err));
+        res.unwrap_or_else(|err| panic!("could not add data dir {}", err));
+
+        for name in [".meshes/a.nif", "b.nif~", "c.bak"] {
+            assert!(vfs.exists(name), "{}", name);
+        }
+    }
+}
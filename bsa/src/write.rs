@@ -1,5 +1,10 @@
-use std::{fs, io::{self, Write, Seek}, path::{Path, PathBuf}, slice::Iter};
+use std::{cell::RefCell, collections::BTreeMap, fs, io::{self, Read, Write, Seek, SeekFrom, ErrorKind}, path::{Path, PathBuf}, rc::Rc, slice::Iter};
+use thiserror::Error;
 use super::bin::DataSource;
+use crate::Reader;
+use crate::progress::Observer;
+use crate::read::{self, ArchiveEntry};
+use crate::tree::Totals;
 
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -14,6 +19,37 @@ impl<D> Dir<D> {
             files: files.into_iter().collect()
         }
     }
+
+    /// A directory with the same files that borrows their data.
+    pub fn by_ref(&self) -> Dir<&D> {
+        Dir {
+            name: self.name.clone(),
+            files: self.files.iter().map(File::by_ref).collect(),
+        }
+    }
+}
+#[cfg(feature = "serde")]
+impl Dir<PathBuf> {
+    /// The directories described by a manifest.
+    /// Relative source paths are resolved against `root`.
+    pub fn from_manifest<P: AsRef<Path>>(manifest: &crate::manifest::Manifest, root: P) -> Vec<Self> {
+        manifest.dirs(root)
+    }
+}
+impl<R: Reader> Dir<ArchiveEntry<R>> {
+    /// The directories of an open archive. Data is extracted from it when the files are written.
+    /// All files need a name. Whether files are compressed is left to the writer.
+    pub fn from_archive(reader: &Rc<RefCell<R>>) -> io::Result<Vec<Self>> {
+        let files = reader.borrow_mut().entries()?
+            .map(|(dir, file)| {
+                if dir.map_or(false, |dir| dir.id.name.is_none()) || file.id.name.is_none() {
+                    return Err(io::Error::new(ErrorKind::InvalidData, InvalidPath(read::path(dir, file))));
+                }
+                Ok((read::path(dir, file), ArchiveEntry::new(reader, file.clone())))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        dirs_from_paths(files)
+    }
 }
 impl<'a, D> IntoIterator for &'a Dir<D> {
     type Item = &'a File<D>;
@@ -37,43 +73,275 @@ impl<D> File<D> {
             data
         }
     }
+
+    pub fn by_ref(&self) -> File<&D> {
+        File {
+            name: self.name.clone(),
+            compressed: self.compressed,
+            data: &self.data,
+        }
+    }
+}
+/// Data of either a loose file or a file of another archive, to mix both in one archive.
+#[derive(Debug)]
+pub enum Source<R> {
+    Path(PathBuf),
+    Entry(ArchiveEntry<R>),
+}
+impl<R> Clone for Source<R> {
+    fn clone(&self) -> Self {
+        match self {
+            Source::Path(path) => Source::Path(path.clone()),
+            Source::Entry(entry) => Source::Entry(entry.clone()),
+        }
+    }
+}
+impl<R> From<PathBuf> for Source<R> {
+    fn from(path: PathBuf) -> Self {
+        Source::Path(path)
+    }
+}
+impl<R> From<ArchiveEntry<R>> for Source<R> {
+    fn from(entry: ArchiveEntry<R>) -> Self {
+        Source::Entry(entry)
+    }
+}
+impl<R: Reader> DataSource for Source<R> {
+    type Read = Box<dyn Read>;
+
+    fn open(&self) -> io::Result<Self::Read> {
+        Ok(match self {
+            Source::Path(path) => Box::new(fs::File::open(path)?),
+            Source::Entry(entry) => Box::new(entry.open()?),
+        })
+    }
+
+    fn size(&self) -> Option<u64> {
+        match self {
+            Source::Path(path) => path.size(),
+            Source::Entry(entry) => entry.size(),
+        }
+    }
+}
+
+/// Statistics about a written archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteStats {
+    /// Number of files whose data was already written for another file.
+    pub duplicates: usize,
+    /// Number of bytes not written because of duplicates.
+    pub bytes_saved: u64,
 }
+
+/// The number of files and their known size.
+pub(crate) fn totals<D: DataSource>(dirs: &[Dir<D>]) -> Totals {
+    dirs.iter()
+        .flat_map(|dir| dir.files.iter())
+        .fold(Totals::default(), |totals, file| Totals {
+            files: totals.files + 1,
+            size: totals.size + file.data.size().unwrap_or(0),
+        })
+}
+
 pub trait Writer {
     type Err = io::Error;
 
     fn write_bsa<DS, D, W>(&self, dirs: DS, out: W) -> Result<(), Self::Err>
+    where
+        D: DataSource,
+        DS: IntoIterator<Item = Dir<D>>,
+        W: Write + Seek,
+    {
+        self.write_bsa_observed(dirs, out, &mut ())
+    }
+
+    /// Write an archive while reporting the progress to an observer, that may also cancel writing.
+    /// The output is left incomplete if writing is cancelled.
+    fn write_bsa_observed<DS, D, W>(&self, dirs: DS, out: W, observer: &mut dyn Observer) -> Result<(), Self::Err>
     where
         D: DataSource,
         DS: IntoIterator<Item = Dir<D>>,
         W: Write + Seek;
+
+    /// Write an archive to an output that can't seek, like stdout or a pipe.
+    /// See [`write_sequential`].
+    fn write_bsa_sequential<D, W>(&self, dirs: &[Dir<D>], out: W) -> Result<(), Self::Err>
+    where
+        D: DataSource,
+        W: Write,
+        Self::Err: From<io::Error>,
+    {
+        write_sequential(out, |out| self.write_bsa(dirs.iter().map(Dir::by_ref), out))
+    }
 }
 
-pub fn list_dir<P: AsRef<Path>>(dir: P) -> io::Result<Vec<Dir<PathBuf>>> {
-    let mut stack = vec![PathBuf::new()];
-    let mut res = vec![];
-    while let Some(path) = stack.pop() {
-        let mut files = vec![];
-        let cwd = dir.as_ref().join(&path);
-        for e in fs::read_dir(cwd)? {
-            let entry = e?;
-            if entry.file_type()?.is_dir() {
-                stack.push([&path, &PathBuf::from(entry.file_name())].iter().collect());
+/// An output that can be written to and seeked in.
+pub trait WriteSeek: Write + Seek {}
+impl<W: Write + Seek> WriteSeek for W {}
+
+#[derive(Debug, Error)]
+#[error("can not write at {0} after {1} bytes have been written")]
+pub struct NotSequential(pub u64, pub u64);
+
+#[derive(Debug, Error)]
+#[error("archive has {1} bytes but {0} bytes were expected, did the data change while writing?")]
+pub struct LayoutChanged(pub u64, pub u64);
+
+/// Write an archive strictly sequentially, without seeking in `out`.
+///
+/// Writers go back to update offsets once they are known. To support this `write`
+/// is called twice. The first pass only keeps track of the size of the archive and
+/// the regions that are overwritten, discarding everything else. The second pass
+/// writes everything to `out` with the overwritten regions already in place.
+/// All data sources are therefore read, and compressed, twice and have to yield the same data both times.
+pub fn write_sequential<W, F, T, E>(mut out: W, mut write: F) -> Result<T, E>
+where
+    W: Write,
+    F: FnMut(&mut dyn WriteSeek) -> Result<T, E>,
+    E: From<io::Error>,
+{
+    let mut layout = Layout::default();
+    write(&mut layout)?;
+    let mut sequential = Sequential {
+        out: &mut out,
+        layout,
+        position: 0,
+        written: 0,
+    };
+    let res = write(&mut sequential)?;
+    if sequential.written != sequential.layout.len {
+        let err = LayoutChanged(sequential.layout.len, sequential.written);
+        return Err(io::Error::new(ErrorKind::InvalidData, err).into());
+    }
+    out.flush()?;
+    Ok(res)
+}
+
+fn seek_to(position: u64, len: u64, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+        SeekFrom::Start(offset) => return Ok(offset),
+        SeekFrom::Current(offset) => (position, offset),
+        SeekFrom::End(offset) => (len, offset),
+    };
+    if offset < 0 {
+        base.checked_sub(offset.unsigned_abs())
+    } else {
+        base.checked_add(offset as u64)
+    }.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"))
+}
+
+/// Records the size of the output and the bytes written over already written bytes.
+#[derive(Default)]
+struct Layout {
+    position: u64,
+    len: u64,
+    patches: BTreeMap<u64, Vec<u8>>,
+    max_patch_len: u64,
+}
+impl Write for Layout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.position < self.len {
+            let overlap = buf.len().min((self.len - self.position) as usize);
+            self.max_patch_len = self.max_patch_len.max(overlap as u64);
+            self.patches.insert(self.position, buf[..overlap].to_vec());
+        }
+        self.position += buf.len() as u64;
+        self.len = self.len.max(self.position);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl Seek for Layout {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_to(self.position, self.len, pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Writes everything exactly once, replacing bytes that will be overwritten
+/// with their final value from the [`Layout`].
+struct Sequential<W> {
+    out: W,
+    layout: Layout,
+    position: u64,
+    written: u64,
+}
+impl<W: Write> Write for Sequential<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.position > self.written {
+            return Err(io::Error::new(ErrorKind::Unsupported, NotSequential(self.position, self.written)));
+        }
+        let end = self.position + buf.len() as u64;
+        if end > self.written {
+            let start = self.written;
+            let new = &buf[(start - self.position) as usize..];
+            let mut patches = self.layout.patches
+                .range(start.saturating_sub(self.layout.max_patch_len)..end)
+                .filter(|(pos, patch)| **pos + patch.len() as u64 > start)
+                .peekable();
+            if patches.peek().is_none() {
+                self.out.write_all(new)?;
             } else {
-                files.push(File {
-                    name: entry.file_name().into_string().unwrap(),
-                    compressed: None,
-                    data: entry.path(),
-                });
+                let mut patched = new.to_vec();
+                for (pos, patch) in patches {
+                    for (i, byte) in patch.iter().enumerate() {
+                        let at = pos + i as u64;
+                        if at >= start && at < end {
+                            patched[(at - start) as usize] = *byte;
+                        }
+                    }
+                }
+                self.out.write_all(&patched)?;
             }
+            self.written = end;
         }
-        if !files.is_empty() {
-            res.push(Dir {
-                name: path.into_os_string().into_string().unwrap(), 
-                files
-            });
+        self.position = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+impl<W: Write> Seek for Sequential<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_to(self.position, self.layout.len, pos)?;
+        Ok(self.position)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid path \"{0}\"")]
+pub struct InvalidPath(pub String);
+
+/// Group files by their directory. Paths may use `/` or `\` as separator.
+/// Empty and `.` components are ignored, paths containing `..` are rejected.
+pub(crate) fn dirs_from_paths<D>(files: Vec<(String, D)>) -> io::Result<Vec<Dir<D>>> {
+    let mut dirs: BTreeMap<String, Dir<D>> = BTreeMap::new();
+    for (path, data) in files {
+        let mut components: Vec<&str> = path.split(|c| c == '/' || c == '\\')
+            .filter(|component| !component.is_empty() && *component != ".")
+            .collect();
+        if components.contains(&"..") {
+            return Err(io::Error::new(ErrorKind::InvalidData, InvalidPath(path)));
         }
+        let file_name = components.pop()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, InvalidPath(path.clone())))?
+            .to_owned();
+        let dir_name = components.join("\\");
+        dirs.entry(dir_name.to_lowercase())
+            .or_insert_with(|| Dir::new(dir_name, []))
+            .files.push(File::new(file_name, data));
     }
-    Ok(res)
+    Ok(dirs.into_values().collect())
+}
+
+/// List all files below a directory using the default [`Scanner`](crate::scan::Scanner).
+pub fn list_dir<P: AsRef<Path>>(dir: P) -> io::Result<Vec<Dir<PathBuf>>> {
+    crate::scan::Scanner::new().scan(dir)
 }
 
 #[cfg(test)]
@@ -100,6 +368,14 @@ pub(crate) mod test {
         Cursor::new(out.into_inner())
     }
 
+    /// A path in the temp directory unique to this process and call, so concurrent tests don't collide.
+    pub fn temp_dir(name: &str) -> PathBuf {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!("{}-{}-{}", name, std::process::id(), id))
+    }
+
     pub fn some_bsa_bytes<W: Writer>() -> Cursor<Vec<u8>>
     where
         W: Default,
@@ -107,4 +383,96 @@ pub(crate) mod test {
     {
         bsa_bytes(W::default(), some_bsa_dirs())
     }
+
+    /// An output that fails on any attempt to seek.
+    struct Pipe(Vec<u8>);
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sequential_bytes<W>(writer: W, dirs: &[Dir<Vec<u8>>]) -> Vec<u8>
+    where
+        W: Writer,
+        W::Err: Display + From<io::Error>,
+    {
+        let mut pipe = Pipe(Vec::new());
+        writer.write_bsa_sequential(dirs, &mut pipe)
+            .unwrap_or_else(|err| panic!("could not write bsa {}", err));
+        pipe.0
+    }
+
+    fn many_bsa_dirs() -> Vec<Dir<Vec<u8>>> {
+        vec![
+            Dir::new("a", [File::new("b", vec![1, 2, 3, 4]), File::new("c", vec![5; 100])]),
+            Dir::new("d\\e", [File::new("f", vec![]), File::new("g", vec![6; 1000])]),
+        ]
+    }
+
+    #[test]
+    fn sequential_matches_seekable_v105() {
+        let dirs = many_bsa_dirs();
+        let writer = || {
+            let mut writer = crate::WriterV105::default();
+            writer.archive_flags |= crate::ArchiveFlagV105::CompressedArchive;
+            writer
+        };
+
+        assert_eq!(sequential_bytes(writer(), &dirs), bsa_bytes(writer(), dirs).into_inner());
+    }
+
+    #[test]
+    fn sequential_matches_seekable_v001() {
+        let dirs = many_bsa_dirs();
+
+        assert_eq!(sequential_bytes(crate::WriterV001::default(), &dirs), bsa_bytes(crate::WriterV001::default(), dirs).into_inner());
+    }
+
+    #[test]
+    fn repack_archive_entries_with_loose_files() {
+        let mut writer = crate::WriterV105::default();
+        writer.archive_flags |= crate::ArchiveFlagV105::CompressedArchive;
+        let bsa = crate::SomeReader::read_bsa(bsa_bytes(writer, many_bsa_dirs()))
+            .unwrap_or_else(|err| panic!("could not open bsa {}", err));
+        let bsa = Rc::new(RefCell::new(bsa));
+        let loose = temp_dir("bsa-repack-loose");
+        fs::write(&loose, [7; 10])
+            .unwrap_or_else(|err| panic!("could not write loose file {}", err));
+
+        let mut dirs: Vec<Dir<Source<_>>> = Dir::from_archive(&bsa)
+            .unwrap_or_else(|err| panic!("could not list bsa {}", err))
+            .into_iter()
+            .map(|dir| Dir::new(dir.name, dir.files.into_iter()
+                .filter(|file| file.name != "c")
+                .map(|file| File { name: file.name, compressed: file.compressed, data: Source::from(file.data) })))
+            .collect();
+        dirs[0].files.push(File::new("loose", Source::from(loose.clone())));
+        let bytes = bsa_bytes(crate::WriterV001::default(), dirs);
+        fs::remove_file(&loose)
+            .unwrap_or_else(|err| panic!("could not remove loose file {}", err));
+
+        let mut repacked = crate::SomeReader::read_bsa(bytes)
+            .unwrap_or_else(|err| panic!("could not open repacked bsa {}", err));
+        for (path, expected) in [("a/b", vec![1, 2, 3, 4]), ("a/loose", vec![7; 10]), ("d/e/g", vec![6; 1000])] {
+            let file = repacked.find(path)
+                .unwrap_or_else(|err| panic!("could not find {} {}", path, err))
+                .unwrap_or_else(|| panic!("{} not found", path));
+            let mut data = Vec::new();
+            repacked.extract(&file, &mut data)
+                .unwrap_or_else(|err| panic!("could not extract {} {}", path, err));
+            assert_eq!(data, expected, "data of {}", path);
+        }
+        let missing = repacked.find("a/c")
+            .unwrap_or_else(|err| panic!("could not find file {}", err));
+        assert!(missing.is_none(), "found removed file");
+    }
+
+    #[test]
+    fn reject_parent_dirs() {
+        assert!(dirs_from_paths(vec![("a/../../b".to_owned(), ())]).is_err(), "parent dir accepted");
+    }
 }
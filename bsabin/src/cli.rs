@@ -1,7 +1,8 @@
 use std::path::PathBuf;
 use clap::{ArgEnum, Clap};
 use glob::Pattern;
-use bsa::{Version, Version10X, BA2Type};
+use bsa::{Codepage, Version, Version10X, BA2Type};
+use bsa::scan::Symlinks;
 
 
 #[derive(Debug, Clap)]
@@ -13,14 +14,21 @@ pub enum Cmds {
     List(List),
     #[clap(aliases = &["x"])]
     Extract(Extract),
+    Cat(Cat),
+    Find(Find),
+    Stats(Stats),
+    Export(Export),
     #[clap(aliases = &["c"])]
     Create(Create),
+    Import(Import),
     #[clap(aliases = &["a"])]
     Add(Add),
     #[clap(aliases = &["m"])]
     Merge(Merge),
     #[clap(aliases = &["d", "r", "remove"])]
     Del(Del),
+    Diff(Diff),
+    Checksum(Checksum),
 }
 impl Cmds {
     pub fn name(&self) -> &'static str {
@@ -28,10 +36,17 @@ impl Cmds {
             Cmds::Info(_) => "info",
             Cmds::List(_) => "list",
             Cmds::Extract(_) => "extract",
+            Cmds::Cat(_) => "cat",
+            Cmds::Find(_) => "find",
+            Cmds::Stats(_) => "stats",
+            Cmds::Export(_) => "export",
             Cmds::Create(_) => "create",
+            Cmds::Import(_) => "import",
             Cmds::Add(_) => "add",
             Cmds::Merge(_) => "merge",
             Cmds::Del(_) => "del",
+            Cmds::Diff(_) => "diff",
+            Cmds::Checksum(_) => "checksum",
         }
     }
 }
@@ -47,6 +62,10 @@ pub struct Info {
     #[clap(short, long)]
     pub verbose: bool,
 
+    /// With "csv" each header field is printed as a row, flags are separated by "|".
+    #[clap(arg_enum, long, default_value = "text")]
+    pub format: OutputFormat,
+
     /// The archive file.
     #[clap(parse(from_os_str))]
     pub file: PathBuf,
@@ -63,6 +82,18 @@ pub struct List {
     #[clap(short, long)]
     pub attributes: bool,
 
+    /// print directories as a tree. With attributes the number of files and the total size of each directory is printed.
+    #[clap(short, long)]
+    pub tree: bool,
+
+    /// "json" prints the directories with their files, "csv" prints one row per file with all attributes.
+    /// Only "text" is supported for trees.
+    #[clap(arg_enum, long, default_value = "text")]
+    pub format: OutputFormat,
+
+    #[clap(flatten)]
+    pub filter: FilterOpts,
+
     /// The archive file.
     #[clap(parse(from_os_str))]
     pub file: PathBuf,
@@ -75,13 +106,47 @@ pub struct Extract {
     #[clap(flatten)]
     pub open_opts: OpenOpts,
 
-    /// Glob patterns that all file names that should be extracted have to match.
-    #[clap(short, long, parse(try_from_str))]
-    pub include: Vec<Pattern>,
+    #[clap(flatten)]
+    pub filter: FilterOpts,
 
-    /// Glob patterns that no file name that should be extracted should to match.
-    #[clap(short, long, parse(try_from_str))]
-    pub exclude: Vec<Pattern>,
+    /// Write a manifest to this file that allows `create --manifest` to rebuild the archive
+    /// from the extracted files. Manifests ending in ".toml" are written as TOML, all others as JSON.
+    #[clap(long, parse(from_os_str))]
+    pub manifest: Option<PathBuf>,
+
+    /// Recover missing names from candidate paths.
+    /// Either a wordlist with one path per line, another archive or a plugin file.
+    #[clap(long, parse(from_os_str), multiple_occurrences = true, number_of_values = 1)]
+    pub names_from: Vec<PathBuf>,
+
+    /// Write the data of all extracted files to stdout, one after another, instead of creating files.
+    #[clap(long, conflicts_with_all = &["manifest", "output", "dry-run"])]
+    pub stdout: bool,
+
+    /// Overwrite existing files.
+    #[clap(long, conflicts_with_all = &["skip-existing", "newer"])]
+    pub overwrite: bool,
+
+    /// Keep existing files.
+    #[clap(long, conflicts_with = "newer")]
+    pub skip_existing: bool,
+
+    /// Only overwrite existing files that were modified before the archive.
+    #[clap(long)]
+    pub newer: bool,
+
+    /// Print which files would be written without writing anything.
+    #[clap(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Extract all files directly into the output directory, without their directories.
+    #[clap(long, conflicts_with_all = &["strip-components", "manifest"])]
+    pub flatten: bool,
+
+    /// Remove this many leading directories from the path of each file.
+    /// Files inside fewer directories are not extracted.
+    #[clap(long, default_value = "0", conflicts_with = "manifest")]
+    pub strip_components: usize,
 
     /// The archive file.
     #[clap(parse(from_os_str))]
@@ -92,21 +157,159 @@ pub struct Extract {
     pub output: PathBuf,
 }
 
+/// Write the uncompressed data of files in an archive to stdout.
+#[derive(Debug, Clap)]
+#[clap()]
+pub struct Cat {
+    #[clap(flatten)]
+    pub open_opts: OpenOpts,
+
+    /// The archive file.
+    #[clap(parse(from_os_str))]
+    pub file: PathBuf,
+
+    /// Complete paths of the files, ignoring case. Both "/" and "\\" are accepted as separator.
+    #[clap(required = true)]
+    pub paths: Vec<String>,
+}
+
+/// Find files in many archives.
+#[derive(Debug, Clap)]
+#[clap()]
+pub struct Find {
+    /// Codepage names are encoded with, like windows-1251 or cp932.
+    #[clap(long, default_value = "windows-1252")]
+    pub codepage: Codepage,
+
+    /// The query is a regular expression searched for in the paths of files, ignoring case.
+    #[clap(short, long, conflicts_with = "hash")]
+    pub regex: bool,
+
+    /// The query is the hash of a file or directory, as printed for entries without a name.
+    #[clap(long)]
+    pub hash: bool,
+
+    #[clap(arg_enum, long, default_value = "text")]
+    pub format: OutputFormat,
+
+    /// Glob pattern matching the complete paths of files, ignoring case.
+    pub query: String,
+
+    /// Archives to search. All archives directly inside of a directory are searched.
+    #[clap(parse(from_os_str), required = true)]
+    pub archives: Vec<PathBuf>,
+}
+
+/// Break an archive down by top level directory and extension.
+/// Every file is extracted to determine its uncompressed size and find duplicates.
+#[derive(Debug, Clap)]
+#[clap()]
+pub struct Stats {
+    #[clap(flatten)]
+    pub open_opts: OpenOpts,
+
+    /// Number of largest files to print.
+    #[clap(long, default_value = "10")]
+    pub largest: usize,
+
+    /// Only "text" and "json" are supported.
+    #[clap(arg_enum, long, default_value = "text")]
+    pub format: OutputFormat,
+
+    /// The archive file.
+    #[clap(parse(from_os_str))]
+    pub file: PathBuf,
+}
+
+/// Convert an archive into a zip or tar archive.
+#[derive(Debug, Clap)]
+#[clap()]
+pub struct Export {
+    #[clap(flatten)]
+    pub open_opts: OpenOpts,
+
+    /// Write a zip archive with deflate compressed files.
+    #[clap(long, conflicts_with = "tar", required_unless_present = "tar")]
+    pub zip: bool,
+
+    /// Write an uncompressed tar archive.
+    #[clap(long)]
+    pub tar: bool,
+
+    /// The file to write. If none is provided the archive name plus ".zip" or ".tar" is used.
+    /// Use "-" to write to stdout.
+    #[clap(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// The archive file.
+    #[clap(parse(from_os_str))]
+    pub file: PathBuf,
+}
+
 /// Create an archive file.
 #[derive(Debug, Clap)]
 #[clap()]
 pub struct Create {
-    /// bsa archive Version or game name.
+    /// bsa archive Version or game name. Can be omitted if a manifest is used.
     #[clap(subcommand)]
-    pub args: CreateArgs,
+    pub args: Option<CreateArgs>,
+
+    /// Build the archive from a manifest, either written by `extract --manifest` or by hand.
+    /// Version, flags, order, compression and sources of files are taken from the manifest.
+    /// Manifests ending in ".toml" are read as TOML, all others as JSON.
+    #[clap(long, parse(from_os_str))]
+    pub manifest: Option<PathBuf>,
 
-    /// The archive file to create. If none is provided the directory name plus ".bsa" is used.
+    /// The archive file to create. If none is provided the directory or manifest name plus ".bsa" is used.
+    /// Use "-" to write the archive to stdout.
     #[clap(short, long, parse(from_os_str))]
     pub output: Option<PathBuf>,
+
+    /// Codepage to encode names with, like windows-1251 or cp932.
+    #[clap(long, default_value = "windows-1252")]
+    pub codepage: Codepage,
+
+    #[clap(flatten)]
+    pub scan_opts: ScanOpts,
     
     /// Root directory of the archive to create.
+    /// When using a manifest, relative sources are resolved against it. Defaults to the directory of the manifest.
     #[clap(parse(from_os_str))]
-    pub file: PathBuf,
+    pub file: Option<PathBuf>,
+}
+
+/// Create an archive from the files of a zip or tar archive without extracting them.
+#[derive(Debug, Clap)]
+#[clap()]
+pub struct Import {
+    /// bsa archive Version or game name of the archive to create.
+    #[clap(arg_enum, long)]
+    pub to: VersionSlug,
+
+    /// Compress the files of the archive. Ignored for v001.
+    #[clap(short, long)]
+    pub compress: bool,
+
+    /// Codepage to encode names with, like windows-1251 or cp932.
+    #[clap(long, default_value = "windows-1252")]
+    pub codepage: Codepage,
+
+    /// Read the input as a zip archive, regardless of its extension.
+    #[clap(long, conflicts_with = "tar")]
+    pub zip: bool,
+
+    /// Read the input as an uncompressed tar archive, regardless of its extension.
+    #[clap(long)]
+    pub tar: bool,
+
+    /// The archive file to create. If none is provided the input name plus ".bsa" is used.
+    /// Use "-" to write the archive to stdout.
+    #[clap(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// The zip or tar archive. Inputs ending in ".tar" are read as tar, all others as zip.
+    #[clap(parse(from_os_str))]
+    pub input: PathBuf,
 }
 
 #[derive(Debug, Clap)]
@@ -136,14 +339,25 @@ pub struct Merge {
 
 
 /// Remove files from an archive.
+/// The archive is rewritten with the same version and flags without the selected files.
 #[derive(Debug, Clap)]
 #[clap()]
 pub struct Del {
-    /// Archive to delet from.
+    #[clap(flatten)]
+    pub open_opts: OpenOpts,
+
+    #[clap(flatten)]
+    pub filter: FilterOpts,
+
+    /// Print which files would be removed without changing the archive.
+    #[clap(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Archive to delete from.
     #[clap(parse(from_os_str))]
     pub file: PathBuf,
 
-    /// glob patterns of files to remove.
+    /// Glob patterns of files to remove, like --include.
     #[clap(parse(try_from_str))]
     pub files: Vec<Pattern>,
 }
@@ -175,13 +389,13 @@ impl From<&CreateArgs> for Version {
 
 #[derive(Debug, PartialEq, Eq, Clone, Clap)]
 pub struct V10XCreateArgs {
-    /// don't include direcotry names.
-    /// Games may not load achrives with this option.
+    /// Don't include directory names.
+    /// Games may not load archives with this option.
     #[clap(long)]
     pub no_dir_names: bool,
 
-    /// don't include file names.
-    /// Games may not load achrives with this option.
+    /// Don't include file names.
+    /// Games may not load archives with this option.
     #[clap(long)]
     pub no_file_names: bool,
 
@@ -189,25 +403,179 @@ pub struct V10XCreateArgs {
     #[clap(short, long)]
     pub compress: bool,
 
-    /// set the retain directories names flag.
+    /// Set the retain directory names flag.
     /// This has no effect on the file structure.
     /// May have unknown effect in games.
-    #[clap(long)]
+    #[clap(long, conflicts_with = "no-dir-names")]
     pub retain_dir_names: bool,
 
-    /// set the retain file names flag.
+    /// Set the retain file names flag.
     /// This has no effect on the file structure.
     /// May have unknown effect in games.
-    #[clap(long)]
+    #[clap(long, conflicts_with = "no-file-names")]
     pub retain_file_names: bool,
 
-    /// create an Xbox360 compatible archive.
+    /// Create an Xbox 360 archive, which stores numbers after the header big-endian.
+    /// Files are still compressed with zlib or lz4, not with the Xbox only XMem codec.
     #[clap(long)]
     pub xbox: bool,
 
-    /// Embed the filenames with the data.
+    /// Embed the file names with the data. Not available for v103.
     #[clap(long)]
     pub embed_file_names: bool,
+
+    /// Store the data of byte identical files only once.
+    /// Can not be used with embedded file names, since the data of every file starts with its name.
+    #[clap(long, conflicts_with = "embed-file-names")]
+    pub deduplicate: bool,
+}
+
+/// Options for collecting the files of the root directory.
+#[derive(Debug, Clap)]
+pub struct ScanOpts {
+    /// Glob patterns that all files that should be added have to match.
+    #[clap(short, long, parse(try_from_str), multiple_occurrences = true, number_of_values = 1)]
+    pub include: Vec<Pattern>,
+
+    /// Glob patterns of files and directories that should not be added.
+    #[clap(short, long, parse(try_from_str), multiple_occurrences = true, number_of_values = 1)]
+    pub exclude: Vec<Pattern>,
+
+    /// Add files and directories starting with a ".".
+    #[clap(long)]
+    pub hidden: bool,
+
+    /// Add editor backups like "foo~" or "foo.bak".
+    #[clap(long)]
+    pub backups: bool,
+
+    /// What to do with symbolic links.
+    #[clap(arg_enum, long, default_value = "follow")]
+    pub symlinks: SymlinkSlug,
+}
+
+#[derive(ArgEnum, Debug, PartialEq, Clone)]
+pub enum SymlinkSlug {
+    Follow,
+    Skip,
+    Error,
+}
+impl From<&SymlinkSlug> for Symlinks {
+    fn from(slug: &SymlinkSlug) -> Self {
+        match slug {
+            SymlinkSlug::Follow => Symlinks::Follow,
+            SymlinkSlug::Skip => Symlinks::Skip,
+            SymlinkSlug::Error => Symlinks::Error,
+        }
+    }
+}
+
+/// Show which files were added, removed, modified or renamed between two archives.
+#[derive(Debug, Clap)]
+#[clap()]
+pub struct Diff {
+    /// Options used to open both archives.
+    #[clap(flatten)]
+    pub open_opts: OpenOpts,
+
+    #[clap(arg_enum, long, default_value = "text")]
+    pub format: OutputFormat,
+
+    /// The old archive file.
+    #[clap(parse(from_os_str))]
+    pub old: PathBuf,
+
+    /// The new archive file.
+    #[clap(parse(from_os_str))]
+    pub new: PathBuf,
+}
+
+/// Print CRC32 and SHA-256 checksums of the uncompressed data of all files.
+#[derive(Debug, Clap)]
+#[clap()]
+pub struct Checksum {
+    #[clap(flatten)]
+    pub open_opts: OpenOpts,
+
+    /// "json" prints a checksum manifest that can be checked with --verify.
+    #[clap(arg_enum, long, default_value = "text")]
+    pub format: OutputFormat,
+
+    /// Check the archive against a checksum manifest instead.
+    /// Fails if files are missing, unexpected or have different data.
+    #[clap(long, parse(from_os_str))]
+    pub verify: Option<PathBuf>,
+
+    /// The archive file.
+    #[clap(parse(from_os_str))]
+    pub file: PathBuf,
+}
+
+#[derive(ArgEnum, Debug, PartialEq, Clone)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+/// Select files of an archive. Without any options all files are selected.
+#[derive(Debug, Clap)]
+pub struct FilterOpts {
+    /// Glob patterns of files to select, ignoring case.
+    /// A pattern matching a directory selects all files below it.
+    #[clap(short, long, parse(try_from_str), multiple_occurrences = true, number_of_values = 1)]
+    pub include: Vec<Pattern>,
+
+    /// Glob patterns of files not to select, even if they are included.
+    #[clap(short, long, parse(try_from_str), multiple_occurrences = true, number_of_values = 1)]
+    pub exclude: Vec<Pattern>,
+
+    /// Regular expressions searched for in paths of files to select, ignoring case.
+    #[clap(long, multiple_occurrences = true, number_of_values = 1)]
+    pub regex: Vec<String>,
+
+    /// Regular expressions searched for in paths of files not to select.
+    #[clap(long, multiple_occurrences = true, number_of_values = 1)]
+    pub exclude_regex: Vec<String>,
+
+    /// Only select files with one of these extensions, like "dds" or ".nif".
+    #[clap(long = "ext", multiple_occurrences = true, number_of_values = 1)]
+    pub extensions: Vec<String>,
+
+    /// Only select files at least this large inside the archive.
+    /// In bytes or with a "k", "M" or "G" suffix for multiples of 1000.
+    #[clap(long, parse(try_from_str = parse_size))]
+    pub min_size: Option<u64>,
+
+    /// Only select files at most this large inside the archive.
+    #[clap(long, parse(try_from_str = parse_size))]
+    pub max_size: Option<u64>,
+
+    /// Only select compressed files.
+    #[clap(long, conflicts_with = "uncompressed")]
+    pub compressed: bool,
+
+    /// Only select uncompressed files.
+    #[clap(long)]
+    pub uncompressed: bool,
+
+    /// Read patterns from a file with one glob pattern per line.
+    /// Lines starting with "!" exclude files, patterns starting with "re:" are regular expressions.
+    /// Empty lines and lines starting with "#" are ignored.
+    #[clap(long, parse(from_os_str))]
+    pub patterns_from: Option<PathBuf>,
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, factor) = match s.char_indices().last() {
+        Some((i, 'k')) | Some((i, 'K')) => (&s[..i], 1_000),
+        Some((i, 'M')) => (&s[..i], 1_000_000),
+        Some((i, 'G')) => (&s[..i], 1_000_000_000),
+        _ => (s, 1),
+    };
+    digits.parse::<u64>().ok()
+        .and_then(|size| size.checked_mul(factor))
+        .ok_or_else(|| format!("invalid size \"{}\"", s))
 }
 
 #[derive(Debug, Clap)]
@@ -215,20 +583,24 @@ pub struct OpenOpts {
     /// Ignore file version information and treat it as this version instead.
     #[clap(arg_enum, long)]
     pub force_version: Option<VersionSlug>,
+
+    /// Codepage names are encoded with, like windows-1251 or cp932.
+    #[clap(long, default_value = "windows-1252")]
+    pub codepage: Codepage,
 }
 
 
 #[derive(ArgEnum, Debug, PartialEq, Clone)]
 pub enum VersionSlug {
-    #[clap(aliases = &["001", "tes3", "morrowind"])]
+    #[clap(alias = "001", alias = "tes3", alias = "morrowind")]
     V001,
-    #[clap(aliases = &["103", "tes4", "oblivion"])]
+    #[clap(alias = "103", alias = "tes4", alias = "oblivion")]
     V103,
-    #[clap(aliases = &["104", "tes5", "skyrim", "fallout3", "f3", "fnv", "newvegas", "falloutnewvegas"])]
+    #[clap(alias = "104", alias = "tes5", alias = "skyrim", alias = "fallout3", alias = "f3", alias = "fnv", alias = "newvegas", alias = "falloutnewvegas")]
     V104,
-    #[clap(aliases = &["105", "tes5se", "skyrimse"])]
+    #[clap(alias = "105", alias = "tes5se", alias = "skyrimse")]
     V105,
-    #[clap(aliases = &["2", "200", "ba2", "fallout4", "f4", "fallout76", "f76"])]
+    #[clap(alias = "2", alias = "200", alias = "ba2", alias = "fallout4", alias = "f4", alias = "fallout76", alias = "f76")]
     BA2,
 }
 impl From<&VersionSlug> for Version {
@@ -242,3 +614,23 @@ impl From<&VersionSlug> for Version {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_with_suffix() {
+        for (s, expected) in &[("12", 12), ("3k", 3_000), ("3K", 3_000), ("4M", 4_000_000), ("5G", 5_000_000_000)] {
+            assert_eq!(parse_size(s), Ok(*expected), "size of {}", s);
+        }
+    }
+
+    #[test]
+    fn parse_size_rejects_invalid_sizes() {
+        for s in &["", "k", "1.5M", "-1", "18446744073709551616", "18446744073709552G"] {
+            assert!(parse_size(s).is_err(), "{} is not rejected", s);
+        }
+    }
+}
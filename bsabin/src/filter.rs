@@ -0,0 +1,285 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use glob::{Pattern, MatchOptions};
+use regex::{Regex, RegexBuilder};
+use bsa::SomeRoot;
+use bsa::read::{self, File};
+
+use crate::cli::FilterOpts;
+
+
+/// Selects files of an archive by their path and attributes.
+/// Paths use `/` as separator and are matched ignoring case.
+#[derive(Debug, Default)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    include_regex: Vec<Regex>,
+    exclude_regex: Vec<Regex>,
+    extensions: Vec<String>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    compressed: Option<bool>,
+}
+impl Filter {
+    const MATCH_OPTS: MatchOptions = MatchOptions {
+        case_sensitive: false,
+        require_literal_leading_dot: false,
+        require_literal_separator: false,
+    };
+
+    /// Build a filter from the options. `include` are additional glob patterns of files to select.
+    pub fn new(opts: &FilterOpts, include: &[Pattern]) -> Result<Self> {
+        let mut filter = Filter {
+            include: opts.include.iter().chain(include).cloned().collect(),
+            exclude: opts.exclude.clone(),
+            include_regex: opts.regex.iter().map(|re| regex(re)).collect::<Result<_>>()?,
+            exclude_regex: opts.exclude_regex.iter().map(|re| regex(re)).collect::<Result<_>>()?,
+            extensions: opts.extensions.iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect(),
+            min_size: opts.min_size,
+            max_size: opts.max_size,
+            compressed: match (opts.compressed, opts.uncompressed) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+        };
+        if let Some(path) = &opts.patterns_from {
+            for line in fs::read_to_string(path)?.lines() {
+                filter.push_line(line.trim())?;
+            }
+        }
+        Ok(filter)
+    }
+
+    fn push_line(&mut self, line: &str) -> Result<()> {
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+        let (exclude, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        match (exclude, line.strip_prefix("re:")) {
+            (false, Some(re)) => self.include_regex.push(regex(re)?),
+            (true, Some(re)) => self.exclude_regex.push(regex(re)?),
+            (false, None) => self.include.push(pattern(line)?),
+            (true, None) => self.exclude.push(pattern(line)?),
+        }
+        Ok(())
+    }
+
+    /// Whether any option selects files by themselves, as opposed to only excluding files.
+    pub fn has_selector(&self) -> bool {
+        !self.include.is_empty()
+            || !self.include_regex.is_empty()
+            || !self.extensions.is_empty()
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.compressed.is_some()
+    }
+
+    /// Whether a file is selected. A file is selected if it matches any include pattern,
+    /// or there are none, no exclude pattern and all attribute filters.
+    pub fn matches(&self, path: &str, file: &File) -> bool {
+        let path = path.trim_matches('/');
+        let included = (self.include.is_empty() && self.include_regex.is_empty())
+            || self.include.iter().any(|pattern| matches_glob(pattern, path))
+            || self.include_regex.iter().any(|re| re.is_match(path));
+        let excluded = self.exclude.iter().any(|pattern| matches_glob(pattern, path))
+            || self.exclude_regex.iter().any(|re| re.is_match(path));
+        let size = file.size as u64;
+        included && !excluded
+            && (self.extensions.is_empty() || self.extensions.iter().any(|ext| has_extension(path, ext)))
+            && self.min_size.map_or(true, |min| size >= min)
+            && self.max_size.map_or(true, |max| size <= max)
+            && self.compressed.map_or(true, |compressed| file.compressed == compressed)
+    }
+
+    /// Remove all files that are not selected and directories left empty.
+    pub fn retain(&self, root: &mut SomeRoot) {
+        self.retain_where(root, true)
+    }
+
+    /// Remove all selected files and directories left empty.
+    pub fn remove(&self, root: &mut SomeRoot) {
+        self.retain_where(root, false)
+    }
+
+    fn retain_where(&self, root: &mut SomeRoot, selected: bool) {
+        match root {
+            SomeRoot::V10X(dirs) => {
+                for dir in dirs.iter_mut() {
+                    let dir_id = dir.id.clone();
+                    dir.files.retain(|file| self.matches(&format!("{}/{}", dir_id, file.id), file) == selected);
+                }
+                dirs.retain(|dir| !dir.files.is_empty());
+            },
+            SomeRoot::V001(files) => files.retain(|file| self.matches(&read::path(None, file), file) == selected),
+        }
+    }
+}
+
+/// A pattern matches a file if it matches its path or any of the directories containing it.
+fn matches_glob(pattern: &Pattern, path: &str) -> bool {
+    pattern.matches_with(path, Filter::MATCH_OPTS)
+        || path.match_indices('/')
+            .any(|(i, _)| pattern.matches_with(&path[..i], Filter::MATCH_OPTS))
+}
+
+fn has_extension(path: &str, ext: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.')
+        .map_or(false, |(_, file_ext)| file_ext.eq_ignore_ascii_case(ext))
+}
+
+fn regex(re: &str) -> Result<Regex> {
+    RegexBuilder::new(re)
+        .case_insensitive(true)
+        .build()
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+}
+
+fn pattern(pattern: &str) -> Result<Pattern> {
+    Pattern::new(pattern)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use clap::Clap;
+    use bsa::{EntryId, Hash};
+    use super::*;
+
+    fn filter(args: &[&str]) -> Filter {
+        let opts = FilterOpts::try_parse_from(["filter"].iter().chain(args))
+            .unwrap_or_else(|err| panic!("could not parse args {}", err));
+        Filter::new(&opts, &[])
+            .unwrap_or_else(|err| panic!("could not create filter {}", err))
+    }
+
+    fn file(size: usize, compressed: bool) -> File {
+        File {
+            id: EntryId { hash: Hash::v10x("a.nif"), name: Some("a.nif".to_owned()) },
+            compressed,
+            offset: 0,
+            size,
+            embedded_name: None,
+        }
+    }
+
+    fn selected(filter: &Filter, paths: &[&str]) -> Vec<String> {
+        paths.iter()
+            .filter(|path| filter.matches(path, &file(1, false)))
+            .map(|path| path.to_string())
+            .collect()
+    }
+
+    const PATHS: &[&str] = &["meshes/armor/a.nif", "meshes/clutter/b.nif", "textures/armor/a.dds", "readme.txt"];
+
+    #[test]
+    fn empty_selects_all() {
+        let filter = filter(&[]);
+        assert!(!filter.has_selector(), "has_selector");
+        assert_eq!(selected(&filter, PATHS), PATHS, "selected");
+    }
+
+    #[test]
+    fn selectors() {
+        assert!(!filter(&[]).has_selector(), "empty");
+        assert!(!filter(&["-e", "meshes", "--exclude-regex", "txt$"]).has_selector(), "exclude only");
+        for args in [&["-i", "meshes"][..], &["--regex", "nif$"], &["--ext", "dds"], &["--min-size", "1"], &["--max-size", "1"], &["--compressed"], &["--uncompressed"]] {
+            assert!(filter(args).has_selector(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = filter(&["-i", "meshes/*", "-e", "*/clutter/*", "--exclude-regex", "\\.txt$"]);
+        assert_eq!(selected(&filter, PATHS), ["meshes/armor/a.nif"], "with include");
+        let filter = self::filter(&["-e", "meshes", "--exclude-regex", "\\.txt$"]);
+        assert_eq!(selected(&filter, PATHS), ["textures/armor/a.dds"], "without include");
+    }
+
+    #[test]
+    fn patterns_match_directory_prefixes_ignoring_case() {
+        let filter = filter(&["-i", "Meshes"]);
+        assert_eq!(selected(&filter, PATHS), ["meshes/armor/a.nif", "meshes/clutter/b.nif"], "top directory");
+        let filter = self::filter(&["-i", "*/ARMOR"]);
+        assert_eq!(selected(&filter, PATHS), ["meshes/armor/a.nif", "textures/armor/a.dds"], "nested directory");
+        let filter = self::filter(&["-i", "meshes/arm"]);
+        assert_eq!(selected(&filter, PATHS), Vec::<String>::new(), "partial directory name");
+    }
+
+    #[test]
+    fn regex_and_extension() {
+        let filter = filter(&["--regex", "^MESHES/.*/a"]);
+        assert_eq!(selected(&filter, PATHS), ["meshes/armor/a.nif"], "regex");
+        let filter = self::filter(&["--ext", ".NIF"]);
+        assert_eq!(selected(&filter, PATHS), ["meshes/armor/a.nif", "meshes/clutter/b.nif"], "extension");
+    }
+
+    #[test]
+    fn patterns_file() {
+        let path = std::env::temp_dir().join(format!("bsa-patterns-{}.txt", std::process::id()));
+        fs::write(&path, "# comment\n\nmeshes\n  textures/*.dds  \n!*/clutter/*\nre:\\.txt$\n!re:^textures/\n")
+            .unwrap_or_else(|err| panic!("could not write patterns {}", err));
+        let filter = filter(&["--patterns-from", path.to_str().unwrap_or_else(|| panic!("non-utf8 temp dir"))]);
+        let _ = fs::remove_file(&path);
+        assert_eq!(filter.include.len(), 2, "include");
+        assert_eq!(filter.exclude.len(), 1, "exclude");
+        assert_eq!(filter.include_regex.len(), 1, "include regex");
+        assert_eq!(filter.exclude_regex.len(), 1, "exclude regex");
+        assert_eq!(selected(&filter, PATHS), ["meshes/armor/a.nif", "readme.txt"], "selected");
+    }
+
+    #[test]
+    fn invalid_patterns_file_line() {
+        let mut filter = Filter::default();
+        let err = filter.push_line("re:(")
+            .err()
+            .unwrap_or_else(|| panic!("invalid regex is not rejected"));
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "regex");
+        let err = filter.push_line("!meshes/[")
+            .err()
+            .unwrap_or_else(|| panic!("invalid pattern is not rejected"));
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "pattern");
+    }
+
+    #[test]
+    fn size_and_compression() {
+        let filter = filter(&["--min-size", "1k", "--max-size", "2k"]);
+        for (size, expected) in &[(999, false), (1_000, true), (2_000, true), (2_001, false)] {
+            assert_eq!(filter.matches("a.nif", &file(*size, false)), *expected, "size {}", size);
+        }
+        let filter = self::filter(&["--compressed"]);
+        assert!(filter.matches("a.nif", &file(1, true)), "compressed with --compressed");
+        assert!(!filter.matches("a.nif", &file(1, false)), "uncompressed with --compressed");
+        let filter = self::filter(&["--uncompressed"]);
+        assert!(!filter.matches("a.nif", &file(1, true)), "compressed with --uncompressed");
+        assert!(filter.matches("a.nif", &file(1, false)), "uncompressed with --uncompressed");
+    }
+
+    #[test]
+    fn remove_drops_empty_dirs() {
+        let dir = |name: &str, files: &[&str]| read::Dir {
+            id: EntryId { hash: Hash::v10x(name), name: Some(name.to_owned()) },
+            files: files.iter()
+                .map(|name| File { id: EntryId { hash: Hash::v10x(name), name: Some(name.to_string()) }, ..file(1, false) })
+                .collect(),
+        };
+        let mut root = SomeRoot::V10X(vec![dir("meshes", &["a.nif"]), dir("textures", &["a.dds", "b.dds"])]);
+        filter(&["-i", "meshes", "-i", "textures/a.dds"]).remove(&mut root);
+        match root {
+            SomeRoot::V10X(dirs) => {
+                assert_eq!(dirs.len(), 1, "dirs");
+                assert_eq!(dirs[0].files.len(), 1, "files");
+                assert_eq!(dirs[0].files[0].id.name.as_deref(), Some("b.dds"), "file");
+            },
+            SomeRoot::V001(_) => panic!("unexpected v001 root"),
+        }
+    }
+}
@@ -1,19 +1,44 @@
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::fs::{self, File};
-use std::io::{BufReader, Result, Error, ErrorKind};
+use std::io::{self, BufReader, BufWriter, Result, Error, ErrorKind, Write};
+use std::ffi::OsStr;
 use clap::Clap;
-use glob::{Pattern, MatchOptions};
 use thiserror::Error;
+use serde::Serialize;
+use glob::Pattern;
+use regex::RegexBuilder;
 
-use bsa::{ArchiveFlagV105, Reader, Writer, WriterV001, WriterV105, EntryId, SomeReader, SomeRoot, Version, list_dir, UnsupportedVersion};
+use bsa::{DataSource, SomeWriter, ArchiveFlagV103, ArchiveFlagV104, ArchiveFlagV105, SomeWriterV10X, ToArchiveBitFlags, Version10X, WriterV103, WriterV104, Entries, Observer, Hash, SomeHeader, ArchiveTree, Codepage, Reader, Writer, WriterV001, WriterV105, EntryId, Names, SomeReader, SomeRoot, Version, Scanner, write, UnsupportedVersion};
+use bsa::scan::Symlinks;
+use bsa::read::ArchiveEntry;
+use bsa::write::WriteSeek;
+use bsa::manifest::Manifest;
+use bsa::tree::TreeDir;
+use bsa::diff::Change;
+use bsa::checksum::{ChecksumManifest, Digest, Mismatch};
 
 mod cli;
-use crate::cli::{Cmds, Info, List, Extract, Create, OpenOpts, CreateArgs};
+mod filter;
+mod progress;
+use crate::filter::Filter;
+use crate::progress::Progress;
+use crate::cli::{Cmds, Info, List, Extract, Cat, Find, Stats, Export, Create, Import, Del, V10XCreateArgs, Diff, Checksum, OpenOpts, CreateArgs, OutputFormat, VersionSlug};
 
 
 fn main() {
+    if let Err(err) = progress::set_ctrlc_handler() {
+        eprintln!("could not handle Ctrl-C, it will abort without cleaning up: {}", err);
+    }
     if let Err(err) = Cmds::parse().exec() {
-        println!("{}", err);
+        // the reader of stdout, like `head`, may stop early
+        if err.kind() == ErrorKind::BrokenPipe {
+            return;
+        }
+        eprintln!("{}", err);
         std::process::exit(1);
     }
 }
@@ -27,7 +52,15 @@ impl Cmd for Cmds {
             Cmds::Info(cmd) => cmd.exec(),
             Cmds::List(cmd) => cmd.exec(),
             Cmds::Extract(cmd) => cmd.exec(),
+            Cmds::Cat(cmd) => cmd.exec(),
+            Cmds::Find(cmd) => cmd.exec(),
+            Cmds::Stats(cmd) => cmd.exec(),
+            Cmds::Export(cmd) => cmd.exec(),
             Cmds::Create(cmd) => cmd.exec(),   
+            Cmds::Import(cmd) => cmd.exec(),
+            Cmds::Del(cmd) => cmd.exec(),
+            Cmds::Diff(cmd) => cmd.exec(),
+            Cmds::Checksum(cmd) => cmd.exec(),
             cmd => Err(Error::new(ErrorKind::Unsupported, format!("Command not supported: {}", cmd.name()))),
         }
     }
@@ -36,147 +69,701 @@ impl Cmd for Cmds {
 impl Cmd for Info {
     fn exec(&self) -> Result<()> {
         let bsa = open(&self.file, &self.open_opts)?;
-        if self.verbose {
-            println!("{:?}", bsa.header());
-        } else {
-            println!("{}", bsa.header());
+        let header = bsa.header();
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        match self.format {
+            OutputFormat::Text if self.verbose => writeln!(out, "{:?}", header)?,
+            OutputFormat::Text => writeln!(out, "{}", header)?,
+            OutputFormat::Json => print_json(&InfoJson {
+                version: header.version(),
+                header: &header,
+            })?,
+            OutputFormat::Csv => {
+                let mut out = csv::Writer::from_writer(io::stdout());
+                out.write_record(["field", "value"])?;
+                out.write_record(["version", &header.version().to_string()])?;
+                if let serde_json::Value::Object(fields) = serde_json::to_value(header)? {
+                    for (field, value) in fields {
+                        out.write_record([field, csv_value(&value)])?;
+                    }
+                }
+                out.flush()?;
+            },
         }
         Ok(())
     }
 }
 
+#[derive(Serialize)]
+struct InfoJson<'a> {
+    version: Version,
+    header: &'a SomeHeader,
+}
+
+/// Write a value as pretty printed json to stdout.
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    serde_json::to_writer_pretty(&mut out, value)?;
+    writeln!(out)
+}
+
+/// A json value as a single csv field.
+fn csv_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(values) => values.iter()
+            .map(csv_value)
+            .collect::<Vec<_>>()
+            .join("|"),
+        value => value.to_string(),
+    }
+}
+
+#[derive(Serialize)]
+struct ListRow<'a> {
+    path: String,
+    dir_hash: Option<Hash>,
+    hash: Hash,
+    size: usize,
+    compressed: bool,
+    offset: u64,
+    embedded_name: Option<&'a str>,
+}
+
 impl Cmd for List {
     fn exec(&self) -> Result<()> {
+        let filter = Filter::new(&self.filter, &[])?;
+        let mut bsa = open(&self.file, &self.open_opts)?;
+        let mut root = bsa.list()?;
+        filter.retain(&mut root);
+        if self.tree {
+            if self.format != OutputFormat::Text {
+                return Err(Error::new(ErrorKind::InvalidInput, "--tree only supports --format text"));
+            }
+            let stdout = io::stdout();
+            return self.print_tree(&mut stdout.lock(), &ArchiveTree::new(&root).root, 0);
+        }
+        match self.format {
+            OutputFormat::Text => {},
+            OutputFormat::Json => {
+                return print_json(&root);
+            },
+            OutputFormat::Csv => {
+                let mut out = csv::Writer::from_writer(io::stdout());
+                for (dir, file) in entries(&root) {
+                    out.serialize(ListRow {
+                        path: match dir {
+                            Some(dir) => format!("{}/{}", &dir.id, &file.id),
+                            None => format!("{}", &file.id),
+                        },
+                        dir_hash: dir.map(|dir| dir.id.hash),
+                        hash: file.id.hash,
+                        size: file.size,
+                        compressed: file.compressed,
+                        offset: file.offset,
+                        embedded_name: file.embedded_name.as_deref(),
+                    })?;
+                }
+                out.flush()?;
+                return Ok(());
+            },
+        }
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for (dir, file) in entries(&root) {
+            let path = match dir {
+                Some(dir) => format!("{}/{}", &dir.id, &file.id),
+                None => format!("{}", &file.id),
+            };
+            if self.attributes {
+                let c = if file.compressed { "c" } else { " " };
+                writeln!(out, "{0} {1: >8} {2}", c, file.size / 1000, path)?;
+            } else {
+                writeln!(out, "{0}", path)?;
+            }
+        }
+        Ok(())
+    }
+}
+impl List {
+    fn print_tree(&self, out: &mut dyn Write, dir: &TreeDir, depth: usize) -> Result<()> {
+        let indent = "  ".repeat(depth);
+        for sub_dir in dir.dirs.values() {
+            if self.attributes {
+                let totals = sub_dir.totals();
+                writeln!(out, "{0}{1}/ ({2} files, {3} kB)", indent, sub_dir.name(), totals.files, totals.size / 1000)?;
+            } else {
+                writeln!(out, "{0}{1}/", indent, sub_dir.name())?;
+            }
+            self.print_tree(out, sub_dir, depth + 1)?;
+        }
+        for file in &dir.files {
+            if self.attributes {
+                let c = if file.file.compressed { "c" } else { " " };
+                writeln!(out, "{0}{1} {2: >8} {3}", indent, c, file.file.size / 1000, file.name())?;
+            } else {
+                writeln!(out, "{0}{1}", indent, file.name())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn entries(root: &SomeRoot) -> Entries<'_> {
+    match root {
+        SomeRoot::V001(files) => Entries::files(files),
+        SomeRoot::V10X(dirs) => Entries::dirs(dirs),
+    }
+}
+
+impl Cmd for Extract {
+    fn exec(&self) -> Result<()> {
+        let filter = Filter::new(&self.filter, &[])?;
+        let names = read_names(&self.names_from, self.open_opts.codepage)?;
         let mut bsa = open(&self.file, &self.open_opts)?;
-        match bsa.list()? {
+        let mut root = bsa.list()?;
+        names.recover(&mut root);
+        filter.retain(&mut root);
+
+        let mut progress = Progress::new();
+        progress.started(ArchiveTree::new(&root).root.totals());
+        if self.stdout {
+            let stdout = io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            match &root {
+                SomeRoot::V10X(dirs) => for dir in dirs {
+                    for file in dir {
+                        bsa.extract_observed(file, &mut out, &mut progress)?;
+                    }
+                },
+                SomeRoot::V001(files) => for file in files {
+                    bsa.extract_observed(file, &mut out, &mut progress)?;
+                },
+            }
+            return out.flush();
+        }
+        let archive_modified = fs::metadata(&self.file)?.modified()?;
+        match &root {
             SomeRoot::V10X(dirs) => {
-                for dir in &dirs {
+                for dir in dirs {
                     for file in dir {
-                        if self.attributes {
-                            let c = if file.compressed { "c" } else { " " };
-                            println!("{0} {1: >8} {2}/{3}", c, file.size / 1000, &dir.id, &file.id);
-                        } else {
-                            println!("{0}/{1}", &dir.id, &file.id);
-                        }
+                        self.extract_entry(&mut bsa, &[&dir.id, &file.id], file, archive_modified, &mut progress)?;
                     }
                 }
             },
             SomeRoot::V001(files) => {
-                for file in &files {
-                    if self.attributes {
-                        println!("  {0: >8} {1}", file.size / 1000, &file.id);
-                    } else {
-                        println!("{0}", &file.id);
-                    }
+                for file in files {
+                    self.extract_entry(&mut bsa, &[&file.id], file, archive_modified, &mut progress)?;
                 }
             },
         }
+        drop(progress);
+
+        if let Some(path) = self.manifest.as_ref().filter(|_| !self.dry_run) {
+            let manifest = Manifest::new(&bsa.header(), &root)?;
+            write_manifest(path, &manifest)?;
+        }
+
         Ok(())
     }
 }
 
+impl Extract {
+    fn extract_entry<R: Reader>(
+        &self,
+        bsa: &mut R,
+        ids: &[&EntryId],
+        file: &bsa::read::File,
+        archive_modified: SystemTime,
+        progress: &mut Progress,
+    ) -> Result<()> {
+        let name = ids.iter().map(ToString::to_string).collect::<Vec<_>>().join("/");
+        let path = match self.output_path(ids)? {
+            Some(path) => path,
+            None => {
+                progress.bytes(file.size as u64);
+                return Ok(());
+            },
+        };
+        let action = self.action(&path, archive_modified)?;
+        if self.dry_run {
+            let note = match action {
+                Action::Create => "",
+                Action::Overwrite => " (overwrite)",
+                Action::Skip => " (skip existing)",
+            };
+            progress.println(format!("{} -> {}{}", name, path.display(), note))?;
+            progress.bytes(file.size as u64);
+            return Ok(());
+        }
+        match action {
+            Action::Skip => {
+                progress.println(format!("{} (skipped, {} exists)", name, path.display()))?;
+                progress.bytes(file.size as u64);
+                Ok(())
+            },
+            Action::Create | Action::Overwrite => {
+                progress.println(name)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                extract_file(bsa, file, &path, progress)
+            },
+        }
+    }
 
-enum FileMatcher {
-    Any,
-    Include(Vec<Pattern>),
-    Exclude(Vec<Pattern>),
-}
-impl FileMatcher {
-    const MATCH_OPTS: MatchOptions = MatchOptions {
-        case_sensitive: false,
-        require_literal_leading_dot: false,
-        require_literal_separator: false,
-    };
-
-    fn new(include: &Vec<Pattern>, exclude: &Vec<Pattern>) -> Result<Self> {
-        Ok(match (include.is_empty(), exclude.is_empty()) {
-            (true, true) => FileMatcher::Any,
-            (false, true) => FileMatcher::Include(include.clone()),
-            (true, false) => FileMatcher::Exclude(exclude.clone()),
-            (false, false) => Err(Error::new(ErrorKind::InvalidInput, "--include can not be used in combination with --exclude"))?,
-        })
+    /// The path to extract a file to, or `None` if `--strip-components` removes all of it.
+    /// Fails if any part of the name could escape the output directory.
+    fn output_path(&self, ids: &[&EntryId]) -> Result<Option<PathBuf>> {
+        let mut parts = Vec::new();
+        for id in ids {
+            match &id.name {
+                Some(name) => parts.extend(name.split(|c| c == '\\' || c == '/')
+                    .filter(|part| !part.is_empty() && *part != ".")
+                    .map(str::to_owned)),
+                None => parts.push(id.hash.to_string()),
+            }
+        }
+        let is_safe = |part: &String| matches!(Path::new(part).components().collect::<Vec<_>>()[..], [Component::Normal(_)]);
+        if !parts.iter().all(is_safe) {
+            let name = ids.iter().map(ToString::to_string).collect::<Vec<_>>().join("/");
+            return Err(Error::new(ErrorKind::InvalidData, UnsafePath(name)));
+        }
+        let file_name = match parts.pop() {
+            Some(file_name) => file_name,
+            None => return Ok(None),
+        };
+        let dirs = if self.flatten {
+            &[][..]
+        } else if self.strip_components <= parts.len() {
+            &parts[self.strip_components..]
+        } else {
+            return Ok(None);
+        };
+        let mut path = self.output.clone();
+        path.extend(dirs);
+        path.push(file_name);
+        Ok(Some(path))
     }
 
-    fn matches(&self, path: &String) -> bool {
-        match self {
-            FileMatcher::Any => true,
-            FileMatcher::Include(patterns) => patterns.iter()
-                .any(|p| FileMatcher::match_single(p, path)),
-            FileMatcher::Exclude(patterns) => patterns.iter()
-                .all(|p| !FileMatcher::match_single(p, path)),
+    fn action(&self, path: &Path, archive_modified: SystemTime) -> Result<Action> {
+        let existing = match fs::metadata(path) {
+            Ok(existing) => existing,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Action::Create),
+            Err(err) => return Err(err),
+        };
+        if self.overwrite {
+            Ok(Action::Overwrite)
+        } else if self.skip_existing {
+            Ok(Action::Skip)
+        } else if self.newer {
+            Ok(if existing.modified()? < archive_modified { Action::Overwrite } else { Action::Skip })
+        } else {
+            Err(Error::new(ErrorKind::AlreadyExists, FileAlreadyExists(path.to_owned())))
         }
     }
+}
+
+/// What to do with a file to extract.
+enum Action {
+    Create,
+    Overwrite,
+    Skip,
+}
 
-    fn match_single(pattern: &Pattern, path: &String) -> bool {
-        pattern.matches_with(&path, FileMatcher::MATCH_OPTS) || path.starts_with(pattern.as_str())
+#[derive(Debug, Error)]
+#[error("{0} would be extracted outside of the output directory")]
+struct UnsafePath(String);
+
+impl Cmd for Cat {
+    fn exec(&self) -> Result<()> {
+        let mut bsa = open(&self.file, &self.open_opts)?;
+        let files = self.paths.iter()
+            .map(|path| bsa.find(path)?
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, bsa::vfs::NotFound(path.clone()))))
+            .collect::<Result<Vec<_>>>()?;
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        for file in &files {
+            bsa.extract(file, &mut out)?;
+        }
+        out.flush()
     }
 }
 
-impl Cmd for Extract {
+impl Cmd for Del {
     fn exec(&self) -> Result<()> {
-        let matcher = FileMatcher::new(&self.include, &self.exclude)?;
-        
+        let filter = Filter::new(&self.filter, &self.files)?;
+        // excluding files alone would select all other files
+        if !filter.has_selector() {
+            return Err(Error::new(ErrorKind::InvalidInput, "no files to delete given, use patterns, --include, --regex, --ext, size or compression options"));
+        }
         let mut bsa = open(&self.file, &self.open_opts)?;
+        let mut root = bsa.list()?;
+        let mut removed = root.clone();
+        filter.retain(&mut removed);
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for (dir, file) in entries(&removed) {
+            writeln!(out, "{}", bsa::read::path(dir, file))?;
+        }
+        if self.dry_run || entries(&removed).next().is_none() {
+            return Ok(());
+        }
 
-        match bsa.list()? {
-            SomeRoot::V10X(dirs) => {
-                for dir in dirs {
-                    for file in &dir {
-                        let file_path = format!("{}/{}", &dir.id, &file.id);
-                        if matcher.matches(&file_path) {
-                            println!("{}", file_path);
-                            let mut out = open_output_file(&self.output, &[&dir.id, &file.id])?;
-                            bsa.extract(&file, &mut out)?;
-                        }
-                    }
+        filter.remove(&mut root);
+        let manifest = Manifest::new(&bsa.header(), &root)?;
+        let writer = manifest.writer(self.open_opts.codepage)?;
+        let mut files: HashMap<String, &bsa::read::File> = entries(&root)
+            .map(|(dir, file)| (path_key(&bsa::read::path(dir, file)), file))
+            .collect();
+        // the remaining files are streamed from the archive, which is closed before it gets replaced
+        let bsa = Rc::new(RefCell::new(bsa));
+        let mut dirs = Vec::new();
+        for dir in &manifest.dirs {
+            let mut write_dir = write::Dir::new(dir.name.clone(), []);
+            for manifest_file in &dir.files {
+                let path = path_key(&format!("{}/{}", dir.name, manifest_file.name));
+                let file = files.remove(&path)
+                    .ok_or_else(|| Error::new(ErrorKind::NotFound, bsa::vfs::NotFound(path.clone())))?;
+                write_dir.files.push(write::File {
+                    name: manifest_file.name.clone(),
+                    compressed: Some(file.compressed),
+                    data: ArchiveEntry::new(&bsa, file.clone()),
+                });
+            }
+            dirs.push(write_dir);
+        }
+
+        let mut tmp = self.file.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        // left over from a failed run
+        match fs::remove_file(&tmp) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+            _ => {},
+        }
+        write_output(&tmp, |out, progress| writer.write_bsa_observed(dirs.iter().map(write::Dir::by_ref), out, progress)
+            .map_err(|err| Error::new(ErrorKind::Other, err)))?;
+        drop(dirs);
+        drop(bsa);
+        fs::rename(&tmp, &self.file)
+    }
+}
+
+/// Paths of files compared ignoring case and separators.
+fn path_key(path: &str) -> String {
+    path.replace('\\', "/").trim_matches('/').to_lowercase()
+}
+
+#[derive(Serialize)]
+struct FoundRow<'a> {
+    archive: String,
+    path: &'a str,
+    size: usize,
+    uncompressed_size: u64,
+    compressed: bool,
+}
+
+impl Cmd for Find {
+    fn exec(&self) -> Result<()> {
+        let archives = self.archives()?;
+        let matches = if self.regex {
+            let re = RegexBuilder::new(&self.query)
+                .case_insensitive(true)
+                .build()
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+            let matcher = move |path: &str, _: Option<&bsa::read::Dir>, _: &bsa::read::File| re.is_match(path.trim_matches('/'));
+            bsa::find::find(&archives, matcher, self.codepage)
+        } else if self.hash {
+            let hash: Hash = self.query.parse()
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+            bsa::find::find(&archives, hash, self.codepage)
+        } else {
+            let pattern = Pattern::new(&self.query.replace('\\', "/"))
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+            bsa::find::find(&archives, pattern, self.codepage)
+        };
+
+        let mut rows = Vec::new();
+        for archive_matches in &matches {
+            match &archive_matches.found {
+                Ok(found) => rows.extend(found.iter().map(|found| FoundRow {
+                    archive: archive_matches.archive.display().to_string(),
+                    path: &found.path,
+                    size: found.file.size,
+                    uncompressed_size: found.uncompressed_size,
+                    compressed: found.file.compressed,
+                })),
+                Err(err) => eprintln!("{}: {}", archive_matches.archive.display(), err),
+            }
+        }
+        if rows.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, bsa::vfs::NotFound(self.query.clone())));
+        }
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        match self.format {
+            OutputFormat::Text => for row in &rows {
+                if row.compressed {
+                    writeln!(out, "{}: {} ({} bytes, {} uncompressed)", row.archive, row.path, row.size, row.uncompressed_size)?;
+                } else {
+                    writeln!(out, "{}: {} ({} bytes)", row.archive, row.path, row.size)?;
                 }
             },
-            SomeRoot::V001(files) => {
-                for file in files {
-                    let file_path = format!("{}", &file.id);
-                    if matcher.matches(&file_path) {
-                        println!("{}", file_path);
-                        let mut out = open_output_file(&self.output, &[&file.id])?;
-                        bsa.extract(&file, &mut out)?;
-                    }
+            OutputFormat::Json => print_json(&rows)?,
+            OutputFormat::Csv => {
+                let mut out = csv::Writer::from_writer(io::stdout());
+                for row in &rows {
+                    out.serialize(row)?;
                 }
+                out.flush()?;
             },
         }
+        Ok(())
+    }
+}
+impl Find {
+    /// The archives to search with directories replaced by the archives inside of them.
+    fn archives(&self) -> Result<Vec<PathBuf>> {
+        let mut archives = Vec::new();
+        for path in &self.archives {
+            if path.is_dir() {
+                let mut dir_archives = Vec::new();
+                for entry in fs::read_dir(path)? {
+                    let entry_path = entry?.path();
+                    let is_bsa = entry_path.extension()
+                        .and_then(OsStr::to_str)
+                        .map_or(false, |ext| ext.eq_ignore_ascii_case("bsa"));
+                    if is_bsa && entry_path.is_file() {
+                        dir_archives.push(entry_path);
+                    }
+                }
+                dir_archives.sort();
+                archives.extend(dir_archives);
+            } else {
+                archives.push(path.clone());
+            }
+        }
+        Ok(archives)
+    }
+}
+
+impl Cmd for Stats {
+    fn exec(&self) -> Result<()> {
+        if self.format == OutputFormat::Csv {
+            return Err(Error::new(ErrorKind::InvalidInput, "stats only supports --format text or json"));
+        }
+        let mut bsa = open(&self.file, &self.open_opts)?;
+        let stats = bsa::stats::Stats::new(&mut bsa, self.largest)?;
+        if self.format == OutputFormat::Json {
+            return print_json(&stats);
+        }
 
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        writeln!(out, "{}", group_line("total", &stats.totals))?;
+        let names = &stats.names;
+        writeln!(out, "names: {} bytes ({} directories, {} files, {} embedded), {:.1}% of stored",
+            names.total(), names.dir_names, names.file_names, names.embedded_names,
+            percent(names.total(), stats.totals.stored))?;
+        writeln!(out)?;
+        writeln!(out, "by directory:")?;
+        for (dir, group) in &stats.by_dir {
+            writeln!(out, "  {}", group_line(if dir.is_empty() { "/" } else { dir }, group))?;
+        }
+        writeln!(out)?;
+        writeln!(out, "by extension:")?;
+        for (ext, group) in &stats.by_extension {
+            writeln!(out, "  {}", group_line(if ext.is_empty() { "(none)" } else { ext }, group))?;
+        }
+        writeln!(out)?;
+        writeln!(out, "largest files:")?;
+        for entry in &stats.largest {
+            writeln!(out, "  {: >10} {}", entry.uncompressed_size, entry.path)?;
+        }
+        if !stats.duplicates.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "duplicates:")?;
+            for duplicates in &stats.duplicates {
+                writeln!(out, "  {} bytes wasted:", duplicates.wasted)?;
+                for entry in &duplicates.files {
+                    writeln!(out, "    {}", entry.path)?;
+                }
+            }
+        }
         Ok(())
     }
 }
 
-fn open(file: &PathBuf, open_opts: &OpenOpts) -> Result<SomeReader<BufReader<File>>> {
-    if let Some(vs) = &open_opts.force_version {
-        Version::from(vs).open(file)
+fn group_line(name: &str, group: &bsa::stats::Group) -> String {
+    format!("{:<16} {: >6} files {: >12} stored {: >12} uncompressed {: >6.1}%",
+        name, group.files, group.stored, group.uncompressed, group.ratio() * 100.0)
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
     } else {
-        bsa::open(file)
+        part as f64 * 100.0 / total as f64
     }
 }
 
-fn open_output_file(out: &PathBuf, ids: &[&EntryId]) -> Result<File> {
-    let mut path = out.clone();
-    for id in ids {
-        path.push(as_path(id));
+impl Cmd for Export {
+    fn exec(&self) -> Result<()> {
+        let mut bsa = open(&self.file, &self.open_opts)?;
+        let ext = if self.zip { "zip" } else { "tar" };
+        let output = self.output.clone()
+            .unwrap_or_else(|| self.file.with_extension(ext));
+        write_output(&output, |out, progress| if self.zip {
+            bsa::convert::to_zip(&mut bsa, out, progress).map(drop)
+        } else {
+            bsa::convert::to_tar(&mut bsa, out, progress).map(drop)
+        })
     }
-    if let Some(parent) = path.parent(){
-        fs::create_dir_all(parent)?;
+}
+
+impl Cmd for Diff {
+    fn exec(&self) -> Result<()> {
+        let mut old = open(&self.old, &self.open_opts)?;
+        let mut new = open(&self.new, &self.open_opts)?;
+        let diff = bsa::Diff::new(&mut old, &mut new)?;
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        match self.format {
+            OutputFormat::Text => {
+                for change in &diff.changes {
+                    match change {
+                        Change::Added(entry) => writeln!(out, "A {}", entry.path)?,
+                        Change::Removed(entry) => writeln!(out, "D {}", entry.path)?,
+                        Change::Modified { old, new } => writeln!(out, "M {} ({} -> {} bytes{})",
+                            new.path,
+                            old.uncompressed_size,
+                            new.uncompressed_size,
+                            if old.compressed == new.compressed { "" } else if new.compressed { ", compressed" } else { ", uncompressed" })?,
+                        Change::Renamed { old, new } => writeln!(out, "R {} -> {}", old.path, new.path)?,
+                    }
+                }
+                writeln!(out, "{} changed, {} unchanged", diff.changes.len(), diff.unchanged)?;
+            },
+            OutputFormat::Json => print_json(&diff)?,
+            OutputFormat::Csv => {
+                let mut out = csv::Writer::from_writer(io::stdout());
+                for change in &diff.changes {
+                    let (kind, old, new) = match change {
+                        Change::Added(entry) => ("added", None, Some(entry)),
+                        Change::Removed(entry) => ("removed", Some(entry), None),
+                        Change::Modified { old, new } => ("modified", Some(old), Some(new)),
+                        Change::Renamed { old, new } => ("renamed", Some(old), Some(new)),
+                    };
+                    out.serialize(DiffRow {
+                        change: kind,
+                        old_path: old.map(|entry| entry.path.as_str()),
+                        new_path: new.map(|entry| entry.path.as_str()),
+                        old_sha256: old.map(|entry| entry.sha256),
+                        new_sha256: new.map(|entry| entry.sha256),
+                    })?;
+                }
+                out.flush()?;
+            },
+        }
+        Ok(())
     }
-    check_exists(&path)?;
-    File::create(path)
 }
 
-fn as_path(id: &EntryId) -> PathBuf {
-    if let Some(name) = &id.name {
-        let mut path = PathBuf::new();
-        for part in name.split("\\") {
-            path.push(part);
+#[derive(Serialize)]
+struct DiffRow<'a> {
+    change: &'a str,
+    old_path: Option<&'a str>,
+    new_path: Option<&'a str>,
+    old_sha256: Option<Digest>,
+    new_sha256: Option<Digest>,
+}
+
+impl Cmd for Checksum {
+    fn exec(&self) -> Result<()> {
+        let mut bsa = open(&self.file, &self.open_opts)?;
+        let manifest = ChecksumManifest::new(&mut bsa)?;
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        if let Some(path) = &self.verify {
+            let expected: ChecksumManifest = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            let mismatches = expected.verify(&manifest);
+            for mismatch in &mismatches {
+                match mismatch {
+                    Mismatch::Missing(entry) => writeln!(out, "missing {}", entry.path)?,
+                    Mismatch::Unexpected(entry) => writeln!(out, "unexpected {}", entry.path)?,
+                    Mismatch::Different { actual, .. } => writeln!(out, "different {}", actual.path)?,
+                }
+            }
+            return if mismatches.is_empty() {
+                writeln!(out, "{} files ok", manifest.files.len())?;
+                Ok(())
+            } else {
+                Err(Error::new(ErrorKind::InvalidData, ChecksumMismatch(mismatches.len())))
+            };
+        }
+        match self.format {
+            OutputFormat::Text => {
+                for entry in &manifest.files {
+                    writeln!(out, "{:08x}  {}  {}", entry.crc32, entry.sha256, entry.path)?;
+                }
+            },
+            OutputFormat::Json => print_json(&manifest)?,
+            OutputFormat::Csv => {
+                let mut out = csv::Writer::from_writer(io::stdout());
+                for entry in &manifest.files {
+                    out.serialize(entry)?;
+                }
+                out.flush()?;
+            },
         }
-        path
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("{0} files do not match")]
+struct ChecksumMismatch(usize);
+
+fn open(file: &PathBuf, open_opts: &OpenOpts) -> Result<SomeReader<BufReader<File>>> {
+    let bsa: SomeReader<_> = if let Some(vs) = &open_opts.force_version {
+        Version::from(vs).open(file)?
     } else {
-        PathBuf::from(format!("{}", id.hash))
+        bsa::open(file)?
+    };
+    Ok(bsa.with_codepage(open_opts.codepage))
+}
+
+fn read_names(paths: &[PathBuf], codepage: Codepage) -> Result<Names> {
+    let mut names = Names::with_codepage(codepage);
+    for path in paths {
+        let ext = path.extension()
+            .and_then(OsStr::to_str)
+            .map(str::to_lowercase);
+        match ext.as_deref() {
+            Some("bsa") => {
+                let mut bsa = bsa::open::<SomeReader<_>, _>(path)?.with_codepage(codepage);
+                names.harvest_root(&bsa.list()?);
+            },
+            Some("esp") | Some("esm") | Some("esl") => names.harvest(File::open(path)?)?,
+            _ => names.read_list(BufReader::new(File::open(path)?))?,
+        }
     }
+    Ok(names)
+}
+
+/// Extract a file, removing the incomplete file if extraction fails or is cancelled.
+fn extract_file<R: Reader>(bsa: &mut R, file: &bsa::read::File, path: &PathBuf, progress: &mut Progress) -> Result<()> {
+    let mut out = File::create(path)?;
+    let res = bsa.extract_observed(file, &mut out, progress);
+    if res.is_err() {
+        drop(out);
+        let _ = fs::remove_file(path);
+    }
+    res
 }
 
 fn check_exists(path: &PathBuf) -> Result<()> {
@@ -194,37 +781,292 @@ struct FileAlreadyExists(PathBuf);
 
 impl Cmd for Create {
     fn exec(&self) -> Result<()> {
-        let output = match self.output.as_ref() {
-            Some(p) => p.clone(),
-            None => {
-                let mut tmp = (&self).file.clone();
-                tmp.set_extension("bsa");
-                tmp.to_owned()
+        if let Some(path) = &self.manifest {
+            return self.create_from_manifest(path);
+        }
+        let root = self.file.as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "either a directory or --manifest is required"))?;
+        let args = self.args.as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "either a version or --manifest is required"))?;
+        let output = self.output_or(root);
+        let dirs = self.scanner().scan(root)?;
+
+        match args {
+            CreateArgs::V001 => {
+                let opts = WriterV001 { codepage: self.codepage };
+                write_output(&output, |out, progress| opts.write_bsa_observed(dirs.iter().map(write::Dir::by_ref), out, progress)
+                    .map_err(|err| Error::new(ErrorKind::Other, err)))?;
+            },
+            CreateArgs::V103(args) => {
+                let mut writer = WriterV103::default();
+                writer.archive_flags = ArchiveFlagV103::to_archive_bit_flags(args.archive_flag_bits(Version10X::V103)?);
+                writer.codepage = self.codepage;
+                writer.deduplicate = args.deduplicate;
+                write_v10x(&output, &dirs, SomeWriterV10X::V103(writer))?;
+            },
+            CreateArgs::V104(args) => {
+                let mut writer = WriterV104::default();
+                writer.archive_flags = ArchiveFlagV104::to_archive_bit_flags(args.archive_flag_bits(Version10X::V104)?);
+                writer.codepage = self.codepage;
+                writer.deduplicate = args.deduplicate;
+                write_v10x(&output, &dirs, SomeWriterV10X::V104(writer))?;
+            },
+            CreateArgs::V105(args) => {
+                let mut writer = WriterV105::default();
+                writer.archive_flags = ArchiveFlagV105::to_archive_bit_flags(args.archive_flag_bits(Version10X::V105)?);
+                writer.codepage = self.codepage;
+                writer.deduplicate = args.deduplicate;
+                write_v10x(&output, &dirs, SomeWriterV10X::V105(writer))?;
             },
+            v => return Err(Error::new(ErrorKind::Unsupported, UnsupportedVersion(Version::from(v)))),
+        }
+        Ok(())
+    }
+}
+fn write_v10x(output: &PathBuf, dirs: &[write::Dir<PathBuf>], writer: SomeWriterV10X) -> Result<()> {
+    let stats = write_output(output, |out, progress| writer.write_bsa_with_stats(dirs.iter().map(write::Dir::by_ref), out, progress))?;
+    let deduplicate = match &writer {
+        SomeWriterV10X::V103(writer) => writer.deduplicate,
+        SomeWriterV10X::V104(writer) => writer.deduplicate,
+        SomeWriterV10X::V105(writer) => writer.deduplicate,
+    };
+    if deduplicate {
+        eprintln!("{} duplicate files, {} bytes saved", stats.duplicates, stats.bytes_saved);
+    }
+    Ok(())
+}
+
+impl V10XCreateArgs {
+    /// The archive flags as bits. Bits of flags available in multiple versions are the same in all of them.
+    fn archive_flag_bits(&self, version: Version10X) -> Result<u32> {
+        if self.embed_file_names && version == Version10X::V103 {
+            return Err(Error::new(ErrorKind::InvalidInput, "v103 archives can not embed file names"));
+        }
+        let flags = [
+            (!self.no_dir_names, ArchiveFlagV104::IncludeDirectoryNames),
+            (!self.no_file_names, ArchiveFlagV104::IncludeFileNames),
+            (self.compress, ArchiveFlagV104::CompressedArchive),
+            (self.retain_dir_names, ArchiveFlagV104::RetainDirectoryNames),
+            (self.retain_file_names, ArchiveFlagV104::RetainFileNames),
+            (self.xbox, ArchiveFlagV104::Xbox360Archive),
+            (self.embed_file_names, ArchiveFlagV104::EmbedFileNames),
+        ];
+        Ok(flags.iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |bits, (_, flag)| bits | *flag as u32))
+    }
+}
+
+impl Create {
+    fn scanner(&self) -> Scanner {
+        let opts = &self.scan_opts;
+        let mut scanner = Scanner::new();
+        scanner.include = opts.include.clone();
+        scanner.exclude = opts.exclude.clone();
+        scanner.hidden = opts.hidden;
+        scanner.backups = opts.backups;
+        scanner.symlinks = Symlinks::from(&opts.symlinks);
+        scanner
+    }
+
+    fn output_or(&self, path: &PathBuf) -> PathBuf {
+        match self.output.as_ref() {
+            Some(p) => p.clone(),
+            None => path.with_extension("bsa"),
+        }
+    }
+
+    fn create_from_manifest(&self, path: &PathBuf) -> Result<()> {
+        let manifest = read_manifest(path)?;
+        let root = match &self.file {
+            Some(root) => root.clone(),
+            None => path.parent().map(PathBuf::from).unwrap_or_default(),
         };
+        let output = self.output_or(path);
+        let writer = manifest.writer(self.codepage)?;
+        let dirs = write::Dir::from_manifest(&manifest, root);
+        write_output(&output, |out, progress| writer.write_bsa_observed(dirs.iter().map(write::Dir::by_ref), out, progress)
+            .map_err(|err| Error::new(ErrorKind::Other, err)))
+    }
+}
 
-        check_exists(&output)?;
-        let dirs = list_dir(&self.file)?;
-        let file = File::create(output)?;
+impl Cmd for Import {
+    fn exec(&self) -> Result<()> {
+        let input = BufReader::new(File::open(&self.input)?);
+        let is_tar = self.tar || (!self.zip && self.input.extension()
+            .and_then(OsStr::to_str)
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("tar")));
+        if is_tar {
+            self.write(bsa::convert::tar_dirs(input)?)
+        } else {
+            self.write(bsa::convert::zip_dirs(input)?)
+        }
+    }
+}
 
-        match &self.args {
-            CreateArgs::V001 => {
-                WriterV001::default().write_bsa( dirs, file)
-                    .map_err(|err| Error::new(ErrorKind::Other, err))?;
+impl Import {
+    fn writer(&self) -> Result<SomeWriter> {
+        Ok(match self.to {
+            VersionSlug::V001 => SomeWriter::V001(WriterV001 { codepage: self.codepage }),
+            VersionSlug::V103 => {
+                let mut writer = WriterV103::default();
+                writer.codepage = self.codepage;
+                if self.compress {
+                    writer.archive_flags |= ArchiveFlagV103::CompressedArchive;
+                }
+                SomeWriter::V10X(SomeWriterV10X::V103(writer))
             },
-            CreateArgs::V105(args) => {
-                let mut opts = WriterV105::default();
-                if args.compress {
-                    opts.archive_flags |= ArchiveFlagV105::CompressedArchive;
+            VersionSlug::V104 => {
+                let mut writer = WriterV104::default();
+                writer.codepage = self.codepage;
+                if self.compress {
+                    writer.archive_flags |= ArchiveFlagV104::CompressedArchive;
                 }
-                
-                if args.embed_file_names {
-                    opts.archive_flags |= ArchiveFlagV105::EmbedFileNames;
+                SomeWriter::V10X(SomeWriterV10X::V104(writer))
+            },
+            VersionSlug::V105 => {
+                let mut writer = WriterV105::default();
+                writer.codepage = self.codepage;
+                if self.compress {
+                    writer.archive_flags |= ArchiveFlagV105::CompressedArchive;
                 }
-                opts.write_bsa(dirs, file)?;
+                SomeWriter::V10X(SomeWriterV10X::V105(writer))
             },
-            v => return Err(Error::new(ErrorKind::Unsupported, UnsupportedVersion(Version::from(v)))),
+            ref v => return Err(Error::new(ErrorKind::Unsupported, UnsupportedVersion(Version::from(v)))),
+        })
+    }
+
+    fn write<D: DataSource>(&self, dirs: Vec<write::Dir<D>>) -> Result<()> {
+        let writer = self.writer()?;
+        let output = self.output.clone()
+            .unwrap_or_else(|| self.input.with_extension("bsa"));
+        write_output(&output, |out, progress| writer.write_bsa_observed(dirs.iter().map(write::Dir::by_ref), out, progress)
+            .map_err(|err| Error::new(ErrorKind::Other, err)))
+    }
+}
+
+/// Write an archive to a new file or to stdout if the path is "-".
+/// An incomplete file is removed if writing fails or is cancelled.
+fn write_output<F, T>(path: &PathBuf, mut write: F) -> Result<T>
+where F: FnMut(&mut dyn WriteSeek, &mut Progress) -> Result<T> {
+    let mut progress = Progress::new();
+    if path.as_os_str() == "-" {
+        let stdout = io::stdout();
+        write::write_sequential(stdout.lock(), |out| write(out, &mut progress))
+    } else {
+        check_exists(path)?;
+        let mut file = File::create(path)?;
+        let res = write(&mut file, &mut progress);
+        if res.is_err() {
+            drop(file);
+            let _ = fs::remove_file(path);
         }
-        Ok(())
+        res
+    }
+}
+
+fn is_toml(path: &PathBuf) -> bool {
+    path.extension().and_then(OsStr::to_str) == Some("toml")
+}
+
+fn read_manifest(path: &PathBuf) -> Result<Manifest> {
+    if is_toml(path) {
+        toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    } else {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+}
+
+fn write_manifest(path: &PathBuf, manifest: &Manifest) -> Result<()> {
+    if is_toml(path) {
+        let toml = toml::to_string_pretty(manifest)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        fs::write(path, toml)
+    } else {
+        Ok(serde_json::to_writer_pretty(File::create(path)?, manifest)?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(args: &[&str]) -> Extract {
+        Extract::try_parse_from(["extract", "a.bsa", "out"].iter().chain(args))
+            .unwrap_or_else(|err| panic!("could not parse args {}", err))
+    }
+
+    fn id(name: &str) -> EntryId {
+        EntryId { hash: Hash::v10x(name), name: Some(name.to_owned()) }
+    }
+
+    fn output_path(cmd: &Extract, dir: &str, file: &str) -> Option<PathBuf> {
+        cmd.output_path(&[&id(dir), &id(file)])
+            .unwrap_or_else(|err| panic!("could not get output path of {}/{} {}", dir, file, err))
+    }
+
+    #[test]
+    fn output_path_joins_dir_and_file() {
+        let path = output_path(&extract(&[]), "meshes\\armor", "a.nif");
+        assert_eq!(path, Some(["out", "meshes", "armor", "a.nif"].iter().collect()));
+    }
+
+    #[test]
+    fn output_path_rejects_parent_dirs() {
+        let cmd = extract(&[]);
+        for (dir, file) in &[("..", "a.nif"), ("meshes\\..\\..", "a.nif"), ("meshes", ".."), ("meshes/../..", "a.nif")] {
+            let err = cmd.output_path(&[&id(dir), &id(file)])
+                .err()
+                .unwrap_or_else(|| panic!("{}/{} is not rejected", dir, file));
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "kind of {}/{}", dir, file);
+        }
+    }
+
+    #[test]
+    fn output_path_keeps_absolute_paths_inside() {
+        let cmd = extract(&[]);
+        for dir in &["\\meshes", "/meshes", "\\\\meshes\\", "./meshes"] {
+            let path = output_path(&cmd, dir, "a.nif");
+            assert_eq!(path, Some(["out", "meshes", "a.nif"].iter().collect()), "path of {}", dir);
+        }
+    }
+
+    #[test]
+    fn output_path_without_name_uses_hash() {
+        let file = EntryId { hash: Hash::v10x("a.nif"), name: None };
+        let path = extract(&[]).output_path(&[&id("meshes"), &file])
+            .unwrap_or_else(|err| panic!("could not get output path {}", err));
+        assert_eq!(path, Some(PathBuf::from("out").join("meshes").join(file.hash.to_string())));
+    }
+
+    #[test]
+    fn output_path_strips_components() {
+        let cmd = extract(&["--strip-components", "1"]);
+        assert_eq!(output_path(&cmd, "meshes\\armor", "a.nif"), Some(["out", "armor", "a.nif"].iter().collect()), "deeper");
+        assert_eq!(output_path(&cmd, "meshes", "a.nif"), Some(["out", "a.nif"].iter().collect()), "same depth");
+        assert_eq!(output_path(&cmd, "", "a.nif"), None, "beyond depth");
+        let cmd = extract(&["--strip-components", "5"]);
+        assert_eq!(output_path(&cmd, "meshes\\armor", "a.nif"), None, "far beyond depth");
+    }
+
+    #[test]
+    fn del_requires_selector() {
+        for args in [&[][..], &["-e", "meshes"], &["--exclude-regex", "nif$"]] {
+            let del = Del::try_parse_from(["del", "missing.bsa"].iter().chain(args))
+                .unwrap_or_else(|err| panic!("could not parse args {}", err));
+            let err = del.exec()
+                .err()
+                .unwrap_or_else(|| panic!("del {:?} is not rejected", args));
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "kind of {:?}", args);
+        }
+    }
+
+    #[test]
+    fn output_path_flattens() {
+        let cmd = extract(&["--flatten"]);
+        assert_eq!(output_path(&cmd, "meshes\\armor", "a.nif"), Some(["out", "a.nif"].iter().collect()), "nested");
+        assert_eq!(output_path(&cmd, "", "a.nif"), Some(["out", "a.nif"].iter().collect()), "root");
     }
 }
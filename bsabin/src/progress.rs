@@ -0,0 +1,68 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use indicatif::{ProgressBar, ProgressStyle};
+use bsa::Observer;
+use bsa::tree::Totals;
+
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Cancel all running operations on the first Ctrl-C and exit on the second.
+/// Can only be called once per process.
+pub fn set_ctrlc_handler() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if CANCELLED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    })
+}
+
+/// Shows a progress bar on stderr, if it is a terminal, and cancels on Ctrl-C.
+pub struct Progress {
+    bar: ProgressBar,
+}
+impl Progress {
+    pub fn new() -> Self {
+        let bar = ProgressBar::new(0)
+            .with_style(ProgressStyle::default_bar()
+                .template("{bar:40} {bytes}/{total_bytes} {wide_msg}"));
+        Self { bar }
+    }
+
+    /// Print a line to stdout without breaking the progress bar.
+    /// The bar is cleared before and redrawn after the line, since it shares the terminal.
+    pub fn println<S: AsRef<str>>(&self, line: S) -> io::Result<()> {
+        if self.bar.is_hidden() {
+            return writeln!(io::stdout().lock(), "{}", line.as_ref());
+        }
+        let pos = self.bar.position();
+        self.bar.finish_and_clear();
+        let res = writeln!(io::stdout().lock(), "{}", line.as_ref());
+        self.bar.reset();
+        self.bar.set_position(pos);
+        res
+    }
+}
+impl Observer for Progress {
+    fn started(&mut self, totals: Totals) {
+        self.bar.set_length(totals.size);
+        self.bar.set_position(0);
+    }
+
+    fn file_started(&mut self, path: &str, _size: Option<u64>) {
+        self.bar.set_message(path.to_owned());
+    }
+
+    fn bytes(&mut self, bytes: u64) {
+        self.bar.inc(bytes);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        CANCELLED.load(Ordering::SeqCst)
+    }
+}
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+    }
+}